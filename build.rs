@@ -0,0 +1,18 @@
+use std::{env, fs, path::Path};
+
+const BUNDLED_DATABASE: &str = "src/nes/cartridge/nes20db.xml";
+
+/// Embeds the ROM database: the NES 2.0 XML export given by NES20DB_XML
+/// (e.g. NES20DB_XML=/path/to/nes20db.xml cargo build), then the bundled entries.
+fn main() {
+  println!("cargo:rerun-if-env-changed=NES20DB_XML");
+  println!("cargo:rerun-if-changed={}", BUNDLED_DATABASE);
+  let mut xml = String::new();
+  if let Ok(export) = env::var("NES20DB_XML") {
+    println!("cargo:rerun-if-changed={}", export);
+    xml = fs::read_to_string(&export).unwrap_or_else(|e| panic!("can't read NES20DB_XML \"{}\": {}", export, e));
+  }
+  xml.push_str(&fs::read_to_string(BUNDLED_DATABASE).expect("can't read the bundled ROM database"));
+  let out = Path::new(&env::var("OUT_DIR").unwrap()).join("nes20db.xml");
+  fs::write(out, xml).expect("can't write the ROM database");
+}
@@ -8,21 +8,25 @@ use std::env;
 use std::error::Error;
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
-use nes::{Nes, DebugEvent, save_state::SaveState};
-use nes::cartridge::Cartridge;
+use nes::{Nes, DebugEvent, save_state::SaveState, region::Region};
+use nes::cartridge::{Cartridge, database, disk, nsf::{self, NsfInfo}};
+use nes::ppu::{Frame, NesColor};
 use nes::controller::{basic::NesController};
-use nes::apu::mixer::Mixer;
+use nes::apu::mixer::{Mixer, AudioStats};
+use nes::apu::wav::WavFormat;
+use nes::apu::{MixerChannel, MIXER_CHANNELS};
+use nes::apu::scope::{self, Scope, SCOPE_LANES, SCOPE_POINTS};
 
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::render::{TextureCreator};
 use sdl2::rect::Rect;
 use sdl2::controller::GameController;
 use sdl2::GameControllerSubsystem;
 use cpal::traits::{DeviceTrait, HostTrait};
 
-const micros_per_frame : u128 = (1_000_000.0 / 60.0988) as u128; 
 
 fn find_sdl_gl_driver() -> Option<u32> {
   for (index, item) in sdl2::render::drivers().enumerate() {
@@ -65,6 +69,117 @@ fn find_controller(game_controller_subsystem: &GameControllerSubsystem) -> Resul
   Ok(controller)
 }
 
+/// NSF player screen: tune info, current track with elapsed time and the track list.
+fn draw_nsf_screen(frame: &mut Frame, nsf: &NsfInfo, song: u8, frames_played: u32, frame_rate: f64) {
+  let white = NesColor {R: 0xFF, G: 0xFF, B: 0xFF};
+  let gray = NesColor {R: 0x90, G: 0x90, B: 0xA0};
+  let yellow = NesColor {R: 0xFF, G: 0xD0, B: 0x40};
+  frame.fill(NesColor {R: 0x10, G: 0x10, B: 0x30});
+
+  let mut y = 8;
+  y += frame.draw_text(8, y, &nsf.title, white);
+  y += frame.draw_text(8, y, &nsf.artist, gray);
+  y += frame.draw_text(8, y, &nsf.copyright, gray);
+  y += 8;
+  let seconds = (frames_played as f64 / frame_rate) as u32;
+  let now_playing = format!("Now playing: {}  {}:{:02}", nsf.track_name(song), seconds / 60, seconds % 60);
+  y += frame.draw_text(8, y, &now_playing, yellow);
+  y += 8;
+
+  let total = nsf.total_songs as usize;
+  let lines = (frame.height - y - 16) / 7;
+  let first = (song as usize).saturating_sub(lines / 2).min(total.saturating_sub(lines));
+  for track in first..(first + lines).min(total) {
+    let current = track == song as usize;
+    let line = format!("{} {}", if current {">"} else {" "}, nsf.track_name(track as u8));
+    y += frame.draw_text(8, y, &line, if current {yellow} else {white});
+  }
+  frame.draw_text(8, frame.height - 12, "Left/Right: previous/next track", gray);
+}
+
+/// Audio debug overlay: buffer fill, resampling ratio and xrun counters.
+fn draw_audio_overlay(frame: &mut Frame, stats: &AudioStats) {
+  let green = NesColor {R: 0x40, G: 0xFF, B: 0x40};
+  let mut y = 4;
+  y += frame.draw_text(4, y, &format!("AUDIO {}HZ", stats.sample_rate), green);
+  y += frame.draw_text(4, y, &format!("FILL {:.2} RATE {:.4}", stats.fill, stats.rate_ratio), green);
+  frame.draw_text(4, y, &format!("UNDERRUNS {} OVERRUNS {}", stats.underruns, stats.overruns), green);
+}
+
+/// Oscilloscope of each channel and the mixed output, with the output spectrum at the bottom.
+fn draw_scope_screen(frame: &mut Frame, scope: &Scope, freqs: &[Option<f64>; 5]) {
+  let white = NesColor {R: 0xFF, G: 0xFF, B: 0xFF};
+  let colors = [
+    NesColor {R: 0xFF, G: 0x60, B: 0x60},
+    NesColor {R: 0xFF, G: 0xB0, B: 0x40},
+    NesColor {R: 0x60, G: 0xC0, B: 0xFF},
+    NesColor {R: 0xC0, G: 0xC0, B: 0xC0},
+    NesColor {R: 0xC0, G: 0x80, B: 0xFF},
+    NesColor {R: 0x60, G: 0xFF, B: 0x60},
+  ];
+  let names = ["PULSE 1", "PULSE 2", "TRIANGLE", "NOISE", "DMC", "MIX"];
+  frame.fill(NesColor {R: 0x08, G: 0x08, B: 0x18});
+
+  let lane_height = 28;
+  for lane in 0..SCOPE_LANES {
+    let top = 4 + lane * (lane_height + 2);
+    frame.fill_rect(0, top + lane_height / 2, frame.width, 1, NesColor {R: 0x20, G: 0x20, B: 0x40});
+    let points = scope.lane(lane);
+    let x_offset = SCOPE_POINTS - points.len();
+    for (x, (min, max)) in points.iter().enumerate() {
+      let y_max = top + ((1.0 - max) * (lane_height - 1) as f32) as usize;
+      let y_min = top + ((1.0 - min) * (lane_height - 1) as f32) as usize;
+      frame.fill_rect(x + x_offset, y_max, 1, y_min - y_max + 1, colors[lane]);
+    }
+    let note = match freqs.get(lane) {
+      Some(Some(freq)) => format!("{} {}HZ", scope::note_name(*freq), freq.round()),
+      Some(None) => String::from("-"),
+      None => String::new(),
+    };
+    frame.draw_text(2, top, &format!("{} {}", names[lane], note), white);
+  }
+
+  let bands = 64;
+  let spectrum_top = 4 + SCOPE_LANES * (lane_height + 2);
+  let spectrum_height = frame.height.saturating_sub(spectrum_top + 4);
+  let bar_width = frame.width / bands;
+  for (band, magnitude) in scope.spectrum(bands).iter().enumerate() {
+    // -60dB to 0dB
+    let db = 20.0 * magnitude.max(1e-6).log10();
+    let height = (((db + 60.0) / 60.0).clamp(0.0, 1.0) * spectrum_height as f32) as usize;
+    frame.fill_rect(band * bar_width, spectrum_top + spectrum_height - height, bar_width - 1, height, colors[5]);
+  }
+}
+
+/// Keys 1 to 6 select pulse 1, pulse 2, triangle, noise, DMC and expansion audio
+fn mixer_channel_key(keycode: Keycode) -> Option<MixerChannel> {
+  let keys = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4, Keycode::Num5, Keycode::Num6];
+  keys.iter().position(|k| *k == keycode).map(|i| MIXER_CHANNELS[i])
+}
+
+fn print_mixer_channels(nes: &Nes) {
+  let channels: Vec<String> = MIXER_CHANNELS.iter()
+    .map(|c| format!("{} {}{:.1}", c.name(), if nes.channel_enabled(*c) {""} else {"(muted) "}, nes.channel_gain(*c)))
+    .collect();
+  println!("Channels: {}", channels.join(", "));
+}
+
+/// `<rom>-<timestamp>.<extension>` when recording from a hotkey
+fn recording_filename(rom_filename: &str, extension: &str) -> String {
+  let base = rom_filename.rsplit_once('.').map_or(rom_filename, |(base, _)| base);
+  let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+  format!("{}-{}.{}", base, timestamp, extension)
+}
+
+fn save_vgm_log(nes: &mut Nes, filename: &str) {
+  if let Some(vgm) = nes.stop_vgm_log() {
+    match std::fs::write(filename, vgm) {
+      Ok(()) => println!("VGM log saved: {}", filename),
+      Err(e) => eprintln!("Can't save the VGM log \"{}\": {}", filename, e),
+    }
+  }
+}
+
 fn main() -> Result<(), Box<dyn Error>>{
   let sdl_context = sdl2::init()?;
   let video_subsystem = sdl_context.video()?;
@@ -83,14 +198,52 @@ fn main() -> Result<(), Box<dyn Error>>{
 
   let args: Vec<String> = env::args().collect();
   println!("{:?}", args);
-  let mut nes_rom: Vec<u8> = vec![0];
-  if args.len() > 1 {
-    nes_rom = rom::nes_rom_load(&args[1])?;
-    println!("rom loaded: {}", &args[1]);
-  }
-  else {
-    nes_rom = rom::nes_rom_load("./roms/Donkey Kong (U) (PRG1) [!p].nes")?;
+  let mut rom_filename = String::from("./roms/Donkey Kong (U) (PRG1) [!p].nes");
+  let mut patch_filename: Option<String> = None;
+  let mut bios_filename: Option<String> = None;
+  let mut record_filename: Option<String> = None;
+  let mut record_format = WavFormat::Pcm16;
+  let mut record_stems = false;
+  let mut vgm_filename: Option<String> = None;
+  let mut region: Option<Region> = None;
+  let mut arg_iter = args.iter().skip(1);
+  while let Some(arg) = arg_iter.next() {
+    match arg.as_str() {
+      "--patch" => {patch_filename = arg_iter.next().cloned();},
+      "--bios" => {bios_filename = arg_iter.next().cloned();},
+      "--record" => {record_filename = arg_iter.next().cloned();},
+      "--record-float" => {record_format = WavFormat::Float32;},
+      "--record-stems" => {record_stems = true;},
+      "--vgm" => {vgm_filename = arg_iter.next().cloned();},
+      "--database" => {
+        let filename = arg_iter.next().cloned().unwrap_or_default();
+        match database::load_external(&filename) {
+          Ok(games) => println!("ROM database loaded: {} ({} games)", filename, games),
+          Err(e) => {
+            eprintln!("Can't load the ROM database \"{}\": {}", filename, e);
+            std::process::exit(1);
+          },
+        }
+      },
+      "--region" => {
+        let name = arg_iter.next().cloned().unwrap_or_default();
+        region = Region::from_name(&name);
+        if region.is_none() {
+          eprintln!("Unknown region \"{}\", expected ntsc, pal or dendy", name);
+          std::process::exit(1);
+        }
+      },
+      _ => {rom_filename = arg.clone();},
+    }
   }
+  let nes_rom = match rom::nes_rom_load(&rom_filename, patch_filename.as_deref()) {
+    Ok(nes_rom) => nes_rom,
+    Err(e) => {
+      eprintln!("Can't load \"{}\": {}", rom_filename, e);
+      std::process::exit(1);
+    },
+  };
+  println!("rom loaded: {}", rom_filename);
 
   //let nes_rom = rom::nes_rom_load("./roms/Donkey Kong Classics (USA, Europe).nes")?;
   //let nes_rom = rom::nes_rom_load("./roms/Donkey Kong (Japan).nes")?;
@@ -106,8 +259,47 @@ fn main() -> Result<(), Box<dyn Error>>{
   let audio_config = audio_device.default_output_config().unwrap();
   println!("Audio default output config: {:?}", audio_config);
 
-  let mut nes = Nes::new(Cartridge::create_from_rom(&nes_rom), Box::new(controller), Mixer::new(audio_device, audio_config))?;
+  let cartridge = if disk::is_disk_image(&nes_rom) {
+    rom::fds_bios_load(&rom_filename, bios_filename.as_deref())
+      .and_then(|bios| Cartridge::create_from_disk(&nes_rom, &bios))
+  }
+  else if nsf::is_nsf(&nes_rom) {
+    Cartridge::create_from_nsf(&nes_rom)
+  }
+  else {
+    Cartridge::create_from_rom(&nes_rom)
+  };
+  let cartridge = match cartridge {
+    Ok(cartridge) => cartridge,
+    Err(e) => {
+      eprintln!("Can't load \"{}\": {}", rom_filename, e);
+      std::process::exit(1);
+    },
+  };
+  if let Some(game) = &cartridge.game {
+    canvas.window_mut().set_title(&format!("NES emulator - {}", game.title))?;
+  }
+  let nsf_info = cartridge.nsf.clone();
+  if let Some(nsf) = &nsf_info {
+    canvas.window_mut().set_title(&format!("NES emulator - {}", nsf.title))?;
+  }
+  let mut nes = match Nes::new(cartridge, Box::new(controller), Mixer::new(audio_device, audio_config), region) {
+    Ok(nes) => nes,
+    Err(e) => {
+      eprintln!("Can't load \"{}\": {}", rom_filename, e);
+      std::process::exit(1);
+    },
+  };
   nes.reset();
+  if vgm_filename.is_some() {
+    nes.start_vgm_log();
+  }
+  if let Some(filename) = &record_filename {
+    match nes.start_recording(filename, record_format, record_stems) {
+      Ok(()) => println!("Recording audio to {}", filename),
+      Err(e) => eprintln!("Can't record to \"{}\": {}", filename, e),
+    }
+  }
   //nes.debug_reset();
   //nes.load_palette("./palettes/ntscpalette.pal")?;
   nes.load_palette("./palettes/SMM Palette 1.0.pal")?;
@@ -130,9 +322,17 @@ fn main() -> Result<(), Box<dyn Error>>{
   let mut show_nametable = false;
   let mut cpu_debug = false;
   let mut frame_nb = 0;
+  let micros_per_frame = (1_000_000.0 / nes.frame_rate()) as u128;
   let mut time = Instant::now();
   let last_time = time;
   let mut save_state: Option<SaveState> = None;
+  let mut nsf_frame = Frame::new(ppu_info.frame_w, ppu_info.frame_h);
+  let mut nsf_frames_played = 0;
+  let mut show_audio_overlay = false;
+  let mut show_scope = false;
+  let mut scope_frame = Frame::new(ppu_info.frame_w, ppu_info.frame_h);
+  let mut selected_channel = MixerChannel::Pulse1;
+  let mut overlay_frame = Frame::new(ppu_info.frame_w, ppu_info.frame_h);
 
   while running {
     for event in event_pump.poll_iter() {
@@ -184,22 +384,120 @@ fn main() -> Result<(), Box<dyn Error>>{
             nes.debug_load_state(&state);
           }
         },
+        Event::KeyDown {keycode: Some(Keycode::E), ..} => {
+          nes.fds_eject_insert();
+        },
+        Event::KeyDown {keycode: Some(Keycode::X), ..} => {
+          nes.fds_switch_side();
+        },
+        Event::KeyDown {keycode: Some(Keycode::K), ..} => {
+          if nes.is_recording() {
+            match nes.stop_recording() {
+              Ok(()) => println!("Recording stopped"),
+              Err(e) => eprintln!("Can't finish the recording: {}", e),
+            }
+          }
+          else {
+            let filename = recording_filename(&rom_filename, "wav");
+            match nes.start_recording(&filename, record_format, record_stems) {
+              Ok(()) => println!("Recording audio to {}", filename),
+              Err(e) => eprintln!("Can't record to \"{}\": {}", filename, e),
+            }
+          }
+        },
+        Event::KeyDown {keycode: Some(Keycode::Minus), ..} => {
+          nes.set_channel_gain(selected_channel, nes.channel_gain(selected_channel) - 0.1);
+          print_mixer_channels(&nes);
+        },
+        Event::KeyDown {keycode: Some(Keycode::Equals), ..} => {
+          nes.set_channel_gain(selected_channel, nes.channel_gain(selected_channel) + 0.1);
+          print_mixer_channels(&nes);
+        },
+        // 1-6: mute a channel, shift+1-6: solo it
+        Event::KeyDown {keycode: Some(keycode), keymod, ..} if mixer_channel_key(keycode).is_some() => {
+          if let Some(channel) = mixer_channel_key(keycode) {
+            selected_channel = channel;
+            if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+              nes.solo_channel(channel);
+            }
+            else {
+              nes.set_channel_enabled(channel, !nes.channel_enabled(channel));
+            }
+            print_mixer_channels(&nes);
+          }
+        },
+        Event::KeyDown {keycode: Some(Keycode::L), ..} => {
+          if nes.is_vgm_logging() {
+            let filename = vgm_filename.take().unwrap_or_else(|| recording_filename(&rom_filename, "vgm"));
+            save_vgm_log(&mut nes, &filename);
+          }
+          else {
+            nes.start_vgm_log();
+            println!("VGM log started");
+          }
+        },
+        Event::KeyDown {keycode: Some(Keycode::G), ..} => {
+          show_scope = !show_scope;
+          nes.set_scope_enabled(show_scope);
+        },
+        Event::KeyDown {keycode: Some(Keycode::O), ..} => {
+          show_audio_overlay = !show_audio_overlay;
+        },
+        Event::KeyDown {keycode: Some(Keycode::Right), ..} => {
+          nes.nsf_next_song();
+          nsf_frames_played = 0;
+        },
+        Event::KeyDown {keycode: Some(Keycode::Left), ..} => {
+          nes.nsf_prev_song();
+          nsf_frames_played = 0;
+        },
         _ => {},
       }
     }
     if run {
-      let elapsed = time.elapsed().as_micros();
-      if elapsed < micros_per_frame {
-        std::thread::sleep(Duration::from_micros((micros_per_frame - elapsed).try_into().unwrap()));
+      if nes.audio_playing() {
+        // paced by the audio clock: wait for the buffer to drain to its target fill
+        while nes.audio_ahead() {
+          std::thread::sleep(Duration::from_millis(1));
+        }
+      }
+      else {
+        let elapsed = time.elapsed().as_micros();
+        if elapsed < micros_per_frame {
+          std::thread::sleep(Duration::from_micros((micros_per_frame - elapsed).try_into().unwrap()));
+        }
       }
       time = Instant::now();
       nes.tick_frame();
       frame_nb += 1;
+      nsf_frames_played += 1;
       //println!("frame: {}", frame_nb);
     }
     canvas.set_draw_color(sdl2::pixels::Color::RGBA(200, 150, 0, 255));
     canvas.clear();
-    let frame = if show_nametable {nes.get_debug_frame()} else {nes.get_frame()};
+    let frame = match (&nsf_info, nes.nsf_song()) {
+      (Some(nsf), Some((song, _))) => {
+        draw_nsf_screen(&mut nsf_frame, nsf, song, nsf_frames_played, nes.frame_rate());
+        &nsf_frame
+      },
+      _ if show_scope && nes.scope().is_some() => {
+        let freqs = nes.channel_frequencies();
+        if let Some(scope) = nes.scope() {
+          draw_scope_screen(&mut scope_frame, scope, &freqs);
+        }
+        &scope_frame
+      },
+      _ if show_nametable => nes.get_debug_frame(),
+      _ => nes.get_frame(),
+    };
+    let frame = if show_audio_overlay {
+      overlay_frame.clone_from(frame);
+      draw_audio_overlay(&mut overlay_frame, &nes.audio_stats());
+      &overlay_frame
+    }
+    else {
+      frame
+    };
     frame_texture.update(None, frame.get_texture_buffer(), frame.width * 4)?;
     canvas.copy(&frame_texture, None, None)?;
     canvas.present();
@@ -207,5 +505,18 @@ fn main() -> Result<(), Box<dyn Error>>{
       //nes.tick_scanline();
     //}
   }
+  if nes.is_vgm_logging() {
+    let filename = vgm_filename.take().unwrap_or_else(|| recording_filename(&rom_filename, "vgm"));
+    save_vgm_log(&mut nes, &filename);
+  }
+  if let Err(e) = nes.stop_recording() {
+    eprintln!("Can't finish the recording: {}", e);
+  }
+  if let Some(image) = nes.fds_modified_image() {
+    match rom::save_disk_diff(&rom_filename, &image) {
+      Ok(patch_filename) => println!("FDS disk saved: {}", patch_filename),
+      Err(e) => eprintln!("Can't save FDS disk: {}", e),
+    }
+  }
   Ok(())
 }
@@ -8,6 +8,7 @@ mod mapper;
 pub mod cartridge;
 pub mod controller;
 mod clock;
+pub mod region;
 
 use std::error::Error;
 
@@ -15,12 +16,15 @@ use bus::Bus;
 use save_state::SaveState;
 use cpu::CPU;
 use ppu::{PPU, PPUInfo};
-use apu::{APU};
-use apu::mixer::Mixer;
+use apu::{APU, MixerChannel};
+use apu::mixer::{Mixer, AudioStats};
+use apu::wav::WavFormat;
+use apu::scope::Scope;
 use cartridge::Cartridge;
-use clock::{Clock, SlaveClock};
+use clock::{Clock, Scheduler, Event};
 use controller::Controller;
-use mapper::{Mapper};
+use mapper::{Mapper, MapperType};
+use region::Region;
 
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
@@ -44,10 +48,8 @@ pub struct Nes {
   ppu: PPU,
   apu: APU,
   bus: Bus,
-  cpu_clock: SlaveClock,
-  ppu_clock: SlaveClock,
-  apu_clock: SlaveClock,
   cartridge: Cartridge,
+  region: Region,
 
   cpu_nmi: bool,
   debug_no_nmi: bool,
@@ -56,22 +58,25 @@ pub struct Nes {
 }
 
 impl Nes {
-  pub fn new(cartridge: Cartridge, controller: Box<dyn Controller>, mixer: Mixer) -> Result<Self, Box<dyn Error>> {
+  /// `region` overrides the one from the cartridge header
+  pub fn new(cartridge: Cartridge, controller: Box<dyn Controller>, mixer: Mixer, region: Option<Region>) -> Result<Self, Box<dyn Error>> {
+    let timing = cartridge.nsf.as_ref().map_or(cartridge.header.timing_type, |nsf| nsf.timing_type);
+    let region = region.unwrap_or_else(|| Region::from_timing(timing));
     let mut new = Self {
       cpu: CPU::new(),
-      ppu: PPU::new(),
-      apu: APU::new(),
-      bus: Bus::new(controller, mixer),
-      cpu_clock: SlaveClock::new(3),
-      ppu_clock: SlaveClock::new(1),
-      apu_clock: SlaveClock::new(6),
+      ppu: PPU::new(region),
+      apu: APU::new(mixer.sample_rate(), region),
+      bus: Bus::new(controller, mixer, Scheduler::new(region.clock_dividers())),
       cartridge,
+      region,
 
       cpu_nmi: false,
       debug_no_nmi: false,
       breakpoint: false,
       mute: false,
     };
+    new.bus.apu_mem.set_pal(region.pal_apu());
+    println!("Region: {}", region);
     let mapper = mapper::load_rom(&new.cartridge)?;
     new.bus.load_mapper(mapper);
     new.bus.mixer.set_mute(new.mute);
@@ -93,6 +98,11 @@ impl Nes {
     self.bus.mapper.debug_print_vec();
   }
 
+  /// Frames per second of the region
+  pub fn frame_rate(&self) -> f64 {
+    self.region.frame_rate()
+  }
+
   pub fn get_frame(&self) -> &ppu::Frame{
     self.ppu.get_frame()
   }
@@ -105,11 +115,18 @@ impl Nes {
     let mut b = false;
 
     self.breakpoint = false;
-    if self.ppu_clock.tick() {
+    let due = self.bus.scheduler.advance();
+    while let Some(event) = self.bus.scheduler.pop_event() {
+      self.run_event(event);
+    }
+    if due.ppu {
       b = self.ppu.tick(&mut self.bus);
     }
 
-    if self.cpu_clock.tick() {
+    if due.cpu {
+      self.bus.cpu_clock();
+      let irq = self.bus.irq_pending();
+      self.cpu.set_irq(irq);
       if self.cpu.tick(&mut self.bus) {
         let (s_index, cycles) = self.ppu.get_cycles_info();
         if self.cpu.debug {
@@ -118,12 +135,18 @@ impl Nes {
         }
       }
     }
-    if self.apu_clock.tick() {
+    if due.apu {
       self.apu.tick(&mut self.bus);
     }
     b
   }
 
+  fn run_event(&mut self, event: Event) {
+    match event {
+      Event::FrameCounterWrite(value) => self.bus.apu_mem.frame_counter_write(value),
+    }
+  }
+
   pub fn tick_n(&mut self, t: u32) {
     for _t in 0..t {
       self.tick();
@@ -149,6 +172,7 @@ impl Nes {
       if self.tick() && self.ppu.get_frame_status() {
         self.bus.input.update();
         self.bus.input.debug_print();
+        self.apu.set_rate_ratio(self.bus.mixer.rate_ratio());
         break;
       }
       if self.breakpoint {
@@ -165,6 +189,80 @@ impl Nes {
   }
   */
 
+  /// Whether enough audio is buffered to wait before emulating the next frame
+  pub fn audio_ahead(&self) -> bool {
+    self.bus.mixer.ahead()
+  }
+
+  pub fn audio_playing(&self) -> bool {
+    self.bus.mixer.is_playing()
+  }
+
+  pub fn audio_stats(&self) -> AudioStats {
+    self.bus.mixer.stats()
+  }
+
+  /// Mutes or unmutes a channel in the output, the emulated `$4015` is left untouched
+  pub fn set_channel_enabled(&mut self, channel: MixerChannel, enabled: bool) {
+    self.apu.set_channel_enabled(channel, enabled);
+  }
+
+  pub fn channel_enabled(&self, channel: MixerChannel) -> bool {
+    self.apu.channel_enabled(channel)
+  }
+
+  pub fn set_channel_gain(&mut self, channel: MixerChannel, gain: f32) {
+    self.apu.set_channel_gain(channel, gain);
+  }
+
+  pub fn channel_gain(&self, channel: MixerChannel) -> f32 {
+    self.apu.channel_gain(channel)
+  }
+
+  pub fn solo_channel(&mut self, channel: MixerChannel) {
+    self.apu.solo_channel(channel);
+  }
+
+  pub fn set_scope_enabled(&mut self, enabled: bool) {
+    self.apu.set_scope_enabled(enabled);
+  }
+
+  /// Channel waveforms and output spectrum, `None` when the scope is disabled
+  pub fn scope(&self) -> Option<&Scope> {
+    self.apu.scope()
+  }
+
+  /// Pulse 1, pulse 2 and triangle pitches, noise and DMC have none
+  pub fn channel_frequencies(&self) -> [Option<f64>; 5] {
+    self.apu.channel_frequencies(&self.bus)
+  }
+
+  /// Logs the APU register writes until `stop_vgm_log`
+  pub fn start_vgm_log(&mut self) {
+    self.bus.apu_mem.start_vgm_log(self.region.cpu_freq());
+  }
+
+  /// Returns the `.vgm` file, `None` when no log was started
+  pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+    self.bus.apu_mem.stop_vgm_log()
+  }
+
+  pub fn is_vgm_logging(&self) -> bool {
+    self.bus.apu_mem.vgm.is_some()
+  }
+
+  pub fn start_recording(&mut self, filename: &str, format: WavFormat, stems: bool) -> std::io::Result<()> {
+    self.apu.start_recording(filename, format, stems)
+  }
+
+  pub fn stop_recording(&mut self) -> std::io::Result<()> {
+    self.apu.stop_recording()
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.apu.is_recording()
+  }
+
   pub fn ppu_rendering_info(&self) -> PPUInfo {
     self.ppu.render_info()
   }
@@ -172,6 +270,56 @@ impl Nes {
   pub fn set_cpu_debug(&mut self, debug: bool) {
    self.cpu.set_debug(debug); 
   }
+
+  /// FDS only: ejects the disk, or inserts it back if the drive is empty.
+  pub fn fds_eject_insert(&mut self) {
+    if let MapperType::Fds(fds) = &mut *self.bus.mapper {
+      fds.eject_insert();
+    }
+  }
+
+  /// FDS only: flips the disk, or moves to the next disk after the last side.
+  pub fn fds_switch_side(&mut self) {
+    if let MapperType::Fds(fds) = &mut *self.bus.mapper {
+      fds.switch_side();
+    }
+  }
+
+  /// NSF only: current song (0-based) and song count.
+  pub fn nsf_song(&self) -> Option<(u8, u8)> {
+    match &*self.bus.mapper {
+      MapperType::NsfPlayer(player) => Some((player.song(), player.total_songs())),
+      _ => None,
+    }
+  }
+
+  /// NSF only: restarts the player on `song` (0-based, wraps around).
+  pub fn nsf_select_song(&mut self, song: u8) {
+    if let MapperType::NsfPlayer(player) = &mut *self.bus.mapper {
+      player.select_song(song);
+      self.cpu.reset(&mut self.bus);
+    }
+  }
+
+  pub fn nsf_next_song(&mut self) {
+    if let Some((song, total)) = self.nsf_song() {
+      self.nsf_select_song(((song as usize + 1) % total as usize) as u8);
+    }
+  }
+
+  pub fn nsf_prev_song(&mut self) {
+    if let Some((song, total)) = self.nsf_song() {
+      self.nsf_select_song(((song as usize + total as usize - 1) % total as usize) as u8);
+    }
+  }
+
+  /// FDS only: the disk image in its file layout, if the game wrote to it.
+  pub fn fds_modified_image(&self) -> Option<Vec<u8>> {
+    match &*self.bus.mapper {
+      MapperType::Fds(fds) => fds.modified_image(),
+      _ => None,
+    }
+  }
 }
 
 impl Nes {
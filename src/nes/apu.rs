@@ -1,77 +1,278 @@
 pub mod memory;
 pub mod channel;
 pub mod mixer;
+pub mod frame_counter;
+pub mod filter;
+pub mod blip;
+pub mod wav;
+pub mod recorder;
+pub mod vgm;
+pub mod scope;
 
-const SAMPLE_STEP: f32 = 40.58 / 2.0;
+/// CPU cycles per APU cycle
+const APU_CYCLE: u32 = 2;
 
 use crate::nes::{
   bus::Bus,
   clock::Clock,
+  mapper::Mapper,
+  region::Region,
 };
 use crate::nes::apu::channel::{Channel, ChannelType};
+use crate::nes::apu::filter::FilterChain;
+use crate::nes::apu::blip::BlipBuffer;
+use crate::nes::apu::recorder::Recorder;
+use crate::nes::apu::wav::WavFormat;
+use crate::nes::apu::scope::{Scope, SCOPE_LANES};
 use crate::nes::apu::channel::{
   pulse::Pulse,
   triangle::Triangle,
+  noise::Noise,
+  dmc::Dmc,
 };
 
+/// Channels of the mixer, the cartridge expansion audio last
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixerChannel {
+  Pulse1,
+  Pulse2,
+  Triangle,
+  Noise,
+  Dmc,
+  Expansion,
+}
+
+pub const MIXER_CHANNELS: [MixerChannel; 6] = [
+  MixerChannel::Pulse1,
+  MixerChannel::Pulse2,
+  MixerChannel::Triangle,
+  MixerChannel::Noise,
+  MixerChannel::Dmc,
+  MixerChannel::Expansion,
+];
+
+impl MixerChannel {
+  pub fn name(&self) -> &'static str {
+    match self {
+      MixerChannel::Pulse1 => "pulse 1",
+      MixerChannel::Pulse2 => "pulse 2",
+      MixerChannel::Triangle => "triangle",
+      MixerChannel::Noise => "noise",
+      MixerChannel::Dmc => "DMC",
+      MixerChannel::Expansion => "expansion",
+    }
+  }
+}
+
+/// Table lookup between entries, for channel outputs scaled by their gain
+fn lookup(table: &[f32], index: f32) -> f32 {
+  let i = index as usize;
+  match table.get(i + 1) {
+    Some(next) => table[i] + (next - table[i]) * index.fract(),
+    None => table[table.len() - 1],
+  }
+}
+
 pub struct APU {
   channels: Vec<ChannelType>,
   debug_count: usize,
-  next_sample_output: u32,
-  step_fract: f32,
-  output: f32,
+  cpu_freq: f64,
+  blip: BlipBuffer,
+  amplitude: f32,
+  samples: Vec<f32>,
+  pulse_table: [f32; 31],
+  tnd_table: [f32; 203],
+  filters: FilterChain,
+  sample_rate: u32,
+  /// Last output of each channel
+  channel_outputs: [usize; 5],
+  recorder: Option<Recorder>,
+  /// Listening controls, the game still sees the channels as they are
+  channel_enabled: [bool; 6],
+  channel_gain: [f32; 6],
+  scope: Option<Scope>,
 }
 
 impl APU {
-  pub fn new() -> Self {
+  /// `sample_rate` is the output device rate
+  pub fn new(sample_rate: u32, region: Region) -> Self {
     Self {
       channels: vec![
         Pulse::new(0x4000, true),
         Pulse::new(0x4004, false),
         Triangle::new(0x4008),
+        Noise::new(0x400C, region.pal_apu()),
+        Dmc::new(0x4010, region.pal_apu()),
       ],
       debug_count: 0,
-      next_sample_output: SAMPLE_STEP.trunc() as u32,
-      step_fract: SAMPLE_STEP.fract(),
-      output: 0f32,
+      cpu_freq: region.cpu_freq(),
+      blip: BlipBuffer::new(region.cpu_freq(), sample_rate as f64),
+      amplitude: 0f32,
+      samples: Vec::new(),
+      pulse_table: Self::pulse_table(),
+      tnd_table: Self::tnd_table(),
+      filters: FilterChain::new(sample_rate as f32),
+      sample_rate,
+      channel_outputs: [0; 5],
+      recorder: None,
+      channel_enabled: [true; 6],
+      channel_gain: [1.0; 6],
+      scope: None,
     }
   }
 
-  pub fn mixer(&mut self, bus: &mut Bus) -> (bool, f32) {
-    bus.apu_mem.tick();
-    let mut r: f32 = 0f32;
-    let has_sound: bool = false;
-    let mut status = bus.apu_mem.status;
+  pub fn set_rate_ratio(&mut self, ratio: f64) {
+    self.blip.set_rate_ratio(ratio);
+  }
+
+  pub fn set_channel_enabled(&mut self, channel: MixerChannel, enabled: bool) {
+    self.channel_enabled[channel as usize] = enabled;
+  }
 
-    for channel in &mut self.channels {
-      if status & 1 == 1 {
-        let temp_r = channel.tick(bus);
-        r += temp_r as f32;
+  pub fn channel_enabled(&self, channel: MixerChannel) -> bool {
+    self.channel_enabled[channel as usize]
+  }
+
+  /// `gain` is clamped to 0.0..=1.0
+  pub fn set_channel_gain(&mut self, channel: MixerChannel, gain: f32) {
+    self.channel_gain[channel as usize] = gain.clamp(0.0, 1.0);
+  }
+
+  pub fn channel_gain(&self, channel: MixerChannel) -> f32 {
+    self.channel_gain[channel as usize]
+  }
+
+  /// Only `channel` stays enabled, soloing it again enables all the channels back
+  pub fn solo_channel(&mut self, channel: MixerChannel) {
+    let solo = self.channel_enabled.iter().enumerate().all(|(i, enabled)| *enabled == (i == channel as usize));
+    for (i, enabled) in self.channel_enabled.iter_mut().enumerate() {
+      *enabled = solo || i == channel as usize;
+    }
+  }
+
+  /// The channel history is only kept while the scope is enabled
+  pub fn set_scope_enabled(&mut self, enabled: bool) {
+    self.scope = if enabled {Some(Scope::new(self.sample_rate))} else {None};
+  }
+
+  pub fn scope(&self) -> Option<&Scope> {
+    self.scope.as_ref()
+  }
+
+  /// Pitch of the pulse and triangle channels from their timer period, `None` when silent
+  pub fn channel_frequencies(&self, bus: &Bus) -> [Option<f64>; 5] {
+    let mut freqs = [None; 5];
+    let channels = [(0x4000, 2, 16.0, 8), (0x4004, 2, 16.0, 8), (0x4008, 1, 32.0, 2)];
+    for (i, (addr, lo, steps, min_period)) in channels.iter().enumerate() {
+      let channel_reg = bus.apu_mem.get_channel_reg(*addr);
+      let period = (((channel_reg[lo + 1] & 0b0000_0111) as u16) << 8) | (channel_reg[*lo] as u16);
+      if bus.apu_mem.length_status & (1 << i) != 0 && period >= *min_period {
+        freqs[i] = Some(self.cpu_freq / (steps * (period as f64 + 1.0)));
       }
-      status >>= 1;
     }
-    self.output += r / 128.0;
-
-    self.next_sample_output -= 1;
-    if self.next_sample_output == 0 {
-      self.step_fract += SAMPLE_STEP.fract();
-      self.next_sample_output = (SAMPLE_STEP.trunc() + self.step_fract.trunc()) as u32;
-      self.step_fract -= self.step_fract.trunc();
-      let ret: f32 = self.output / SAMPLE_STEP;
-      self.output = 0.0;
-      (true, ret)
+    freqs
+  }
+
+  /// Records the output at the device rate, `stems` also records each channel to its own file.
+  pub fn start_recording(&mut self, filename: &str, format: WavFormat, stems: bool) -> std::io::Result<()> {
+    let recorder = Recorder::create(filename, self.cpu_freq, self.sample_rate, format, stems)?;
+    self.stop_recording()?;
+    self.recorder = Some(recorder);
+    Ok(())
+  }
+
+  pub fn stop_recording(&mut self) -> std::io::Result<()> {
+    match self.recorder.take() {
+      Some(mut recorder) => recorder.finish(),
+      None => Ok(()),
+    }
+  }
+
+  pub fn is_recording(&self) -> bool {
+    self.recorder.is_some()
+  }
+
+  fn record(&mut self) -> std::io::Result<()> {
+    if let Some(recorder) = &mut self.recorder {
+      let [pulse1, pulse2, triangle, noise, dmc] = self.channel_outputs;
+      let amplitudes = [
+        self.pulse_table[pulse1],
+        self.pulse_table[pulse2],
+        self.tnd_table[3 * triangle],
+        self.tnd_table[2 * noise],
+        self.tnd_table[dmc],
+      ];
+      recorder.record(self.amplitude, &amplitudes, APU_CYCLE)?;
+    }
+    Ok(())
+  }
+
+  /// Nonlinear pulse mixing: 95.52 / (8128 / (pulse1 + pulse2) + 100)
+  fn pulse_table() -> [f32; 31] {
+    let mut table = [0f32; 31];
+    for (n, out) in table.iter_mut().enumerate().skip(1) {
+      *out = 95.52 / (8128.0 / (n as f32) + 100.0);
+    }
+    table
+  }
+
+  /// Nonlinear triangle, noise and DMC mixing: 163.67 / (24329 / (3 * triangle + 2 * noise + dmc) + 100)
+  fn tnd_table() -> [f32; 203] {
+    let mut table = [0f32; 203];
+    for (n, out) in table.iter_mut().enumerate().skip(1) {
+      *out = 163.67 / (24329.0 / (n as f32) + 100.0);
+    }
+    table
+  }
+
+  pub fn mixer(&mut self, bus: &mut Bus) -> f32 {
+    // disabled channels are silenced by their length counter
+    for (i, channel) in self.channels.iter_mut().enumerate() {
+      self.channel_outputs[i] = channel.tick(bus) as usize;
     }
-    else {
-      (false, 0f32)
+    bus.apu_mem.clear_frame_clock();
+    let mut levels = [0f32; 6];
+    for (i, level) in levels.iter_mut().enumerate() {
+      if self.channel_enabled[i] {
+        *level = self.channel_gain[i];
+      }
     }
+    let [pulse1, pulse2, triangle, noise, dmc] = self.channel_outputs;
+    let pulse = (pulse1 as f32) * levels[0] + (pulse2 as f32) * levels[1];
+    let tnd = 3.0 * (triangle as f32) * levels[2] + 2.0 * (noise as f32) * levels[3] + (dmc as f32) * levels[4];
+    let r = lookup(&self.pulse_table, pulse) + lookup(&self.tnd_table, tnd);
+    r + bus.mapper.expansion_audio() * levels[5] / 128.0
   }
 }
 
 impl Clock<()> for APU {
   fn tick(&mut self, bus: &mut Bus) -> () {
-    let (mix, r) = self.mixer(bus);
-    if mix {
-      bus.mixer.add_to_stream(r);
+    let amplitude = self.mixer(bus);
+    self.blip.add_delta(amplitude - self.amplitude);
+    self.amplitude = amplitude;
+    self.blip.advance(APU_CYCLE);
+    if let Some(scope) = &mut self.scope {
+      let [pulse1, pulse2, triangle, noise, dmc] = self.channel_outputs;
+      let mut levels = [0f32; SCOPE_LANES];
+      levels[..4].copy_from_slice(&[pulse1 as f32 / 15.0, pulse2 as f32 / 15.0, triangle as f32 / 15.0, noise as f32 / 15.0]);
+      levels[4] = dmc as f32 / 127.0;
+      levels[5] = amplitude.clamp(0.0, 1.0);
+      scope.push_levels(&levels);
+    }
+
+    self.blip.read_samples(&mut self.samples);
+    for sample in self.samples.iter_mut() {
+      *sample = self.filters.process(*sample);
+      bus.mixer.add_to_stream(*sample);
+      if let Some(scope) = &mut self.scope {
+        scope.push_sample(*sample);
+      }
+    }
+    self.samples.clear();
+    // recorded even when the mixer is muted
+    if let Err(e) = self.record() {
+      eprintln!("Recording stopped: {}", e);
+      self.recorder = None;
     }
   }
 }
@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Taps on each side of a step
+const HALF_WIDTH: usize = 8;
+const WIDTH: usize = HALF_WIDTH * 2;
+/// Sub-sample positions of the step kernels
+const PHASES: usize = 64;
+/// Kernel cutoff, relative to the output Nyquist frequency
+const CUTOFF: f64 = 0.9;
+
+/// Band-limited step synthesis: amplitude changes timed in clocks are added as
+/// windowed sinc impulses at the output rate, and integrated back into steps when read.
+pub struct BlipBuffer {
+  /// Output samples per clock
+  factor: f64,
+  base_factor: f64,
+  /// Current time in output samples, relative to the first pending sample
+  time: f64,
+  kernels: Vec<[f32; WIDTH]>,
+  pending: VecDeque<f32>,
+  integrator: f32,
+}
+
+impl BlipBuffer {
+  pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+    Self {
+      factor: sample_rate / clock_rate,
+      base_factor: sample_rate / clock_rate,
+      // starts at the end of the first kernel, no impulse lands before the first sample
+      time: (HALF_WIDTH - 1) as f64,
+      kernels: (0..PHASES).map(Self::kernel).collect(),
+      pending: VecDeque::with_capacity(WIDTH * 4),
+      integrator: 0.0,
+    }
+  }
+
+  /// Blackman windowed sinc impulse for a step at `phase / PHASES` after a sample, normalized to 1.
+  fn kernel(phase: usize) -> [f32; WIDTH] {
+    let frac = phase as f64 / PHASES as f64;
+    let mut taps = [0f64; WIDTH];
+    for (k, tap) in taps.iter_mut().enumerate() {
+      let x = (k as f64) - (HALF_WIDTH as f64 - 1.0) - frac;
+      let sinc = if x == 0.0 {1.0} else {(PI * CUTOFF * x).sin() / (PI * CUTOFF * x)};
+      let w = (x / HALF_WIDTH as f64 + 1.0) / 2.0;
+      let window = if (0.0..=1.0).contains(&w) {
+        0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos()
+      }
+      else {
+        0.0
+      };
+      *tap = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    let mut kernel = [0f32; WIDTH];
+    for (out, tap) in kernel.iter_mut().zip(taps.iter()) {
+      *out = (tap / sum) as f32;
+    }
+    kernel
+  }
+
+  /// Scales the output rate, used to follow the audio device clock
+  pub fn set_rate_ratio(&mut self, ratio: f64) {
+    self.factor = self.base_factor * ratio;
+  }
+
+  /// Adds an amplitude step at the current time
+  pub fn add_delta(&mut self, delta: f32) {
+    if delta == 0.0 {
+      return;
+    }
+    let base = self.time.floor();
+    let phase = (((self.time - base) * PHASES as f64) as usize).min(PHASES - 1);
+    let start = base as usize + 1 - HALF_WIDTH;
+    if self.pending.len() < start + WIDTH {
+      self.pending.resize(start + WIDTH, 0.0);
+    }
+    for (k, tap) in self.kernels[phase].iter().enumerate() {
+      self.pending[start + k] += delta * tap;
+    }
+  }
+
+  pub fn advance(&mut self, clocks: u32) {
+    self.time += clocks as f64 * self.factor;
+  }
+
+  /// Moves the samples no later step can change to `out`
+  pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+    while self.time >= HALF_WIDTH as f64 {
+      self.integrator += self.pending.pop_front().unwrap_or(0.0);
+      out.push(self.integrator);
+      self.time -= 1.0;
+    }
+  }
+}
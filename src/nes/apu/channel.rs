@@ -1,5 +1,10 @@
 pub mod pulse;
 pub mod triangle;
+pub mod noise;
+pub mod dmc;
+pub mod envelope;
+pub mod length_counter;
+pub mod sweep;
 
 use enum_dispatch::enum_dispatch;
 
@@ -7,6 +12,8 @@ use crate::nes::bus::Bus;
 
 use pulse::Pulse;
 use triangle::Triangle;
+use noise::Noise;
+use dmc::Dmc;
 
 #[derive(Debug, Clone)]
 pub struct NullChannel {}
@@ -18,8 +25,8 @@ pub enum ChannelType{
   NullChannel,
   Pulse,
   Triangle,
-  //Noise,
-  //DMC,
+  Noise,
+  Dmc,
 }
 
 #[enum_dispatch(ChannelType)]
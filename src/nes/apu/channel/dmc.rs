@@ -0,0 +1,135 @@
+use crate::nes::{
+  apu::channel::{Channel, ChannelType},
+  bus::Bus,
+};
+
+/// Output rates in CPU cycles
+const RATE_TABLE_NTSC: [u16; 16] = [
+  428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const RATE_TABLE_PAL: [u16; 16] = [
+  398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+const DMC_STATUS: u8 = 0b0001_0000;
+const FLAG_IRQ_ENABLE: u8 = 0b1000_0000;
+const FLAG_LOOP: u8 = 0b0100_0000;
+
+/// Delta modulation channel, the sample bytes are fetched by DMA through the `Bus`.
+#[derive(Clone)]
+pub struct Dmc {
+  addr_first_reg: usize,
+  rate_table: &'static [u16; 16],
+  timer: u16,
+  output: u8,
+  shift_register: u8,
+  bits_remaining: u8,
+  silence: bool,
+  sample_buffer: Option<u8>,
+  current_addr: usize,
+  bytes_remaining: u16,
+}
+
+impl Channel for Dmc {
+  fn tick(&mut self, bus: &mut Bus) -> u8 {
+    self.handle_channel(bus)
+  }
+}
+
+impl Dmc {
+  pub fn new(addr_first_reg: usize, pal: bool) -> ChannelType {
+    let new = Self {
+      addr_first_reg,
+      rate_table: if pal {&RATE_TABLE_PAL} else {&RATE_TABLE_NTSC},
+      timer: 0,
+      output: 0,
+      shift_register: 0,
+      bits_remaining: 8,
+      silence: true,
+      sample_buffer: None,
+      current_addr: 0xC000,
+      bytes_remaining: 0,
+    };
+    new.into()
+  }
+
+  /// Sample address is $C000 + A * 64, length L * 16 + 1
+  fn restart(&mut self, sample_addr: u8, sample_len: u8) {
+    self.current_addr = 0xC000 + (sample_addr as usize) * 64;
+    self.bytes_remaining = (sample_len as u16) * 16 + 1;
+  }
+
+  fn clock_output(&mut self) {
+    if !self.silence {
+      if self.shift_register & 1 != 0 {
+        if self.output <= 125 {
+          self.output += 2;
+        }
+      }
+      else if self.output >= 2 {
+        self.output -= 2;
+      }
+    }
+    self.shift_register >>= 1;
+    self.bits_remaining -= 1;
+    if self.bits_remaining == 0 {
+      self.bits_remaining = 8;
+      match self.sample_buffer.take() {
+        Some(value) => {
+          self.silence = false;
+          self.shift_register = value;
+        },
+        None => self.silence = true,
+      }
+    }
+  }
+
+  fn handle_channel(&mut self, bus: &mut Bus) -> u8 {
+    let channel_reg = bus.apu_mem.get_channel_reg(self.addr_first_reg);
+    let (flags, sample_addr, sample_len) = (channel_reg[0], channel_reg[2], channel_reg[3]);
+
+    if let Some(level) = bus.apu_mem.dmc_direct_load.take() {
+      self.output = level;
+    }
+    if bus.apu_mem.dmc_enable_write {
+      bus.apu_mem.dmc_enable_write = false;
+      if bus.apu_mem.status & DMC_STATUS == 0 {
+        self.bytes_remaining = 0;
+        bus.apu_mem.dmc_dma_addr = None;
+      }
+      else if self.bytes_remaining == 0 {
+        self.restart(sample_addr, sample_len);
+      }
+    }
+
+    // memory reader
+    if let Some(value) = bus.apu_mem.dmc_dma_data.take() {
+      self.sample_buffer = Some(value);
+      if self.bytes_remaining > 0 {
+        self.current_addr = if self.current_addr == 0xFFFF {0x8000} else {self.current_addr + 1};
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+          if flags & FLAG_LOOP != 0 {
+            self.restart(sample_addr, sample_len);
+          }
+          else if flags & FLAG_IRQ_ENABLE != 0 {
+            bus.apu_mem.dmc_irq = true;
+          }
+        }
+      }
+    }
+    if self.sample_buffer.is_none() && self.bytes_remaining > 0 && bus.apu_mem.dmc_dma_addr.is_none() {
+      bus.apu_mem.dmc_dma_addr = Some(self.current_addr);
+    }
+
+    // the timer is clocked every APU cycle, i.e. every other CPU cycle
+    if self.timer <= 2 {
+      self.timer += self.rate_table[(flags & 0b0000_1111) as usize];
+      self.clock_output();
+    }
+    self.timer -= 2;
+
+    bus.apu_mem.set_length_status(DMC_STATUS, self.bytes_remaining > 0);
+    self.output
+  }
+}
@@ -0,0 +1,47 @@
+/// Volume envelope, clocked on quarter frames.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+  start: bool,
+  divider: u8,
+  decay: u8,
+}
+
+impl Envelope {
+  pub fn new() -> Self {
+    Self {
+      start: false,
+      divider: 0,
+      decay: 0,
+    }
+  }
+
+  /// Called on writes to the channel's 4th register
+  pub fn restart(&mut self) {
+    self.start = true;
+  }
+
+  /// `period` is the volume/period nibble, `looping` the length counter halt flag.
+  pub fn clock(&mut self, period: u8, looping: bool) {
+    if self.start {
+      self.start = false;
+      self.decay = 15;
+      self.divider = period;
+    }
+    else if self.divider == 0 {
+      self.divider = period;
+      if self.decay > 0 {
+        self.decay -= 1;
+      }
+      else if looping {
+        self.decay = 15;
+      }
+    }
+    else {
+      self.divider -= 1;
+    }
+  }
+
+  pub fn volume(&self, period: u8, constant_volume: bool) -> u8 {
+    if constant_volume {period} else {self.decay}
+  }
+}
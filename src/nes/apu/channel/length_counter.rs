@@ -0,0 +1,37 @@
+const LENGTH_TABLE: [u8; 32] = [
+  10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+  12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// Length counter, clocked on half frames, silences the channel when it reaches 0.
+#[derive(Debug, Clone)]
+pub struct LengthCounter {
+  counter: u8,
+}
+
+impl LengthCounter {
+  pub fn new() -> Self {
+    Self {
+      counter: 0,
+    }
+  }
+
+  /// `index` is the 5-bit value written to the channel's 4th register
+  pub fn load(&mut self, index: u8) {
+    self.counter = LENGTH_TABLE[(index & 0b0001_1111) as usize];
+  }
+
+  pub fn clear(&mut self) {
+    self.counter = 0;
+  }
+
+  pub fn clock(&mut self, halt: bool) {
+    if !halt && self.counter > 0 {
+      self.counter -= 1;
+    }
+  }
+
+  pub fn active(&self) -> bool {
+    self.counter > 0
+  }
+}
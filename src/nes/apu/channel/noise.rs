@@ -0,0 +1,93 @@
+use crate::nes::{
+  apu::channel::{Channel, ChannelType},
+  apu::channel::envelope::Envelope,
+  apu::channel::length_counter::LengthCounter,
+  bus::Bus,
+};
+
+/// Timer periods in CPU cycles
+const PERIOD_TABLE_NTSC: [u16; 16] = [
+  4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PERIOD_TABLE_PAL: [u16; 16] = [
+  4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+const NOISE_STATUS: u8 = 0b0000_1000;
+
+#[derive(Clone)]
+pub struct Noise {
+  addr_first_reg: usize,
+  period_table: &'static [u16; 16],
+  timer: u16,
+  shift_register: u16,
+  envelope: Envelope,
+  length_counter: LengthCounter,
+}
+
+impl Channel for Noise {
+  fn tick(&mut self, bus: &mut Bus) -> u8 {
+    self.handle_channel(bus)
+  }
+}
+
+impl Noise {
+  pub fn new(addr_first_reg: usize, pal: bool) -> ChannelType {
+    let new = Self {
+      addr_first_reg,
+      period_table: if pal {&PERIOD_TABLE_PAL} else {&PERIOD_TABLE_NTSC},
+      timer: 0,
+      shift_register: 1,
+      envelope: Envelope::new(),
+      length_counter: LengthCounter::new(),
+    };
+    new.into()
+  }
+
+  /// 15-bit LFSR, the feedback is bit 0 xor bit 6 in short mode, bit 0 xor bit 1 otherwise.
+  fn clock_shift_register(&mut self, short_mode: bool) {
+    let tap = if short_mode {6} else {1};
+    let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 1;
+    self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+  }
+
+  fn handle_channel(&mut self, bus: &mut Bus) -> u8 {
+    let channel_reg = bus.apu_mem.get_channel_reg(self.addr_first_reg);
+    let (volume_reg, period_reg, length_reg) = (channel_reg[0], channel_reg[1], channel_reg[2]);
+    let enabled = bus.apu_mem.status & NOISE_STATUS != 0;
+
+    if bus.apu_mem.take_length_reload(NOISE_STATUS) {
+      self.envelope.restart();
+      if enabled {
+        self.length_counter.load(length_reg >> 3);
+      }
+    }
+    if !enabled {
+      self.length_counter.clear();
+    }
+
+    let looping = volume_reg & 0b0010_0000 != 0;
+    let frame_clock = bus.apu_mem.frame_clock;
+    if frame_clock.quarter {
+      self.envelope.clock(volume_reg & 0b0000_1111, looping);
+    }
+    if frame_clock.half {
+      self.length_counter.clock(looping);
+    }
+
+    // the timer is clocked every APU cycle, i.e. every other CPU cycle
+    if self.timer <= 2 {
+      self.timer += self.period_table[(period_reg & 0b0000_1111) as usize];
+      self.clock_shift_register(period_reg & 0b1000_0000 != 0);
+    }
+    self.timer -= 2;
+
+    bus.apu_mem.set_length_status(NOISE_STATUS, self.length_counter.active());
+    if self.shift_register & 1 != 0 || !self.length_counter.active() {
+      0
+    }
+    else {
+      self.envelope.volume(volume_reg & 0b0000_1111, volume_reg & 0b0001_0000 != 0)
+    }
+  }
+}
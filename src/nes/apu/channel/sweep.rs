@@ -0,0 +1,59 @@
+/// Pulse period sweep, clocked on half frames.
+#[derive(Debug, Clone)]
+pub struct Sweep {
+  /// Pulse 1 negates with one's complement, pulse 2 with two's complement
+  ones_complement: bool,
+  divider: u8,
+  reload: bool,
+}
+
+impl Sweep {
+  pub fn new(ones_complement: bool) -> Self {
+    Self {
+      ones_complement,
+      divider: 0,
+      reload: false,
+    }
+  }
+
+  /// Called on writes to the sweep register
+  pub fn restart(&mut self) {
+    self.reload = true;
+  }
+
+  /// `reg` is the sweep register: EPPP NSSS
+  pub fn target_period(&self, period: u16, reg: u8) -> u16 {
+    let change = period >> (reg & 0b0000_0111);
+    if reg & 0b0000_1000 != 0 {
+      let change = if self.ones_complement {change + 1} else {change};
+      period.saturating_sub(change)
+    }
+    else {
+      period + change
+    }
+  }
+
+  /// The channel is silenced even when the sweep is disabled
+  pub fn muting(&self, period: u16, reg: u8) -> bool {
+    period < 8 || self.target_period(period, reg) > 0x7FF
+  }
+
+  /// Returns the new period when the sweep updates it
+  pub fn clock(&mut self, period: u16, reg: u8) -> Option<u16> {
+    let enabled = reg & 0b1000_0000 != 0 && reg & 0b0000_0111 != 0;
+    let new_period = if self.divider == 0 && enabled && !self.muting(period, reg) {
+      Some(self.target_period(period, reg))
+    }
+    else {
+      None
+    };
+    if self.divider == 0 || self.reload {
+      self.divider = (reg >> 4) & 0b0000_0111;
+      self.reload = false;
+    }
+    else {
+      self.divider -= 1;
+    }
+    new_period
+  }
+}
@@ -1,7 +1,6 @@
-use std::fmt;
-
 use crate::nes::{
   apu::channel::{Channel, ChannelType},
+  apu::channel::length_counter::LengthCounter,
   bus::Bus,
 };
 
@@ -10,43 +9,16 @@ const SEQUENCE_LOOKUP_TABLE: [u8; 32] = [
   0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
 ];
 
-#[derive(Debug, Clone)]
-#[allow(non_snake_case)]
-struct Register {
-  control_flag: bool,
-  linear_counter_load: u8,
-  timer: u16,
-  lenght_counter_load: u8,
-}
-
-impl fmt::Display for Register {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "{:?}", self)
-  }
-}
-
-impl Register {
-  pub fn new() -> Self {
-    Self {
-      control_flag: false,
-      linear_counter_load: 0,
-      timer: 0,
-      lenght_counter_load: 0,
-    }
-  }
-
-  fn load_from_channel_reg(&mut self, channel_reg: &[u8]) {
-    self.control_flag = channel_reg[0] & 0b1000_0000 != 0;
-    self.linear_counter_load = channel_reg[0] & 0b0111_1111;
-
-    self.lenght_counter_load = channel_reg[2] >> 3;
-  }
-}
+const TRIANGLE_STATUS: u8 = 0b0000_0100;
 
 #[derive(Clone)]
 pub struct Triangle {
   addr_first_reg: usize,
-  reg: Register,
+  timer: u16,
+  sequence_index: usize,
+  linear_counter: u8,
+  linear_reload: bool,
+  length_counter: LengthCounter,
 }
 
 impl Channel for Triangle {
@@ -59,22 +31,68 @@ impl Triangle {
   pub fn new(addr_first_reg: usize) -> ChannelType {
     let new = Self {
       addr_first_reg,
-      reg: Register::new(),
+      timer: 0,
+      sequence_index: 0,
+      linear_counter: 0,
+      linear_reload: false,
+      length_counter: LengthCounter::new(),
     };
     new.into()
   }
 
+  /// Clocked on quarter frames
+  fn clock_linear_counter(&mut self, linear_reg: u8) {
+    if self.linear_reload {
+      self.linear_counter = linear_reg & 0b0111_1111;
+    }
+    else if self.linear_counter > 0 {
+      self.linear_counter -= 1;
+    }
+    if linear_reg & 0b1000_0000 == 0 {
+      self.linear_reload = false;
+    }
+  }
+
   fn handle_channel(&mut self, bus: &mut Bus) -> u8 {
     let channel_reg = bus.apu_mem.get_channel_reg(self.addr_first_reg);
-    self.reg.load_from_channel_reg(&channel_reg);
+    let (linear_reg, timer_lo, timer_hi) = (channel_reg[0], channel_reg[1], channel_reg[2]);
+    let period = (((timer_hi & 0b0000_0111) as u16) << 8) | (timer_lo as u16);
+    let control = linear_reg & 0b1000_0000 != 0;
+    let enabled = bus.apu_mem.status & TRIANGLE_STATUS != 0;
 
-    if self.reg.timer == 0 {
-      self.reg.timer = (((channel_reg[2] & 0b0000_0111) as u16) << 8) | (channel_reg[1] as u16);
+    if bus.apu_mem.take_length_reload(TRIANGLE_STATUS) {
+      self.linear_reload = true;
+      if enabled {
+        self.length_counter.load(timer_hi >> 3);
+      }
     }
-    else {
-      self.reg.timer -= 1;
+    if !enabled {
+      self.length_counter.clear();
     }
-    let seq = SEQUENCE_LOOKUP_TABLE[self.reg.timer as usize % 32];
-    seq
+
+    let frame_clock = bus.apu_mem.frame_clock;
+    if frame_clock.quarter {
+      self.clock_linear_counter(linear_reg);
+    }
+    if frame_clock.half {
+      self.length_counter.clock(control);
+    }
+
+    // the timer is clocked every CPU cycle, twice per APU cycle
+    for _ in 0..2 {
+      if self.timer == 0 {
+        self.timer = period;
+        // ultrasonic periods are not stepped, the output holds instead of aliasing
+        if self.linear_counter > 0 && self.length_counter.active() && period >= 2 {
+          self.sequence_index = (self.sequence_index + 1) % 32;
+        }
+      }
+      else {
+        self.timer -= 1;
+      }
+    }
+
+    bus.apu_mem.set_length_status(TRIANGLE_STATUS, self.length_counter.active());
+    SEQUENCE_LOOKUP_TABLE[self.sequence_index]
   }
 }
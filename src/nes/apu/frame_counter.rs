@@ -0,0 +1,94 @@
+/// Step cycles of the sequencer in CPU cycles, the last one resets it.
+const STEPS_4_NTSC: [u32; 6] = [7457, 14913, 22371, 29828, 29829, 29830];
+const STEPS_5_NTSC: [u32; 6] = [7457, 14913, 22371, 29829, 37281, 37282];
+const STEPS_4_PAL: [u32; 6] = [8313, 16627, 24939, 33252, 33253, 33254];
+const STEPS_5_PAL: [u32; 6] = [8313, 16627, 24939, 33253, 41565, 41566];
+
+/// Quarter and half frame clocks produced by a sequencer step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameClock {
+  pub quarter: bool,
+  pub half: bool,
+}
+
+/// Frame sequencer, clocked by the CPU and configured by $4017.
+#[derive(Debug, Clone)]
+pub struct FrameCounter {
+  steps: [&'static [u32; 6]; 2],
+  cycle: u32,
+  five_step: bool,
+  irq_inhibit: bool,
+  pub irq: bool,
+  odd_cycle: bool,
+  /// CPU cycles before the last $4017 write takes effect, and its value
+  pub pending_write: Option<(u32, u8)>,
+}
+
+impl FrameCounter {
+  pub fn new(pal: bool) -> Self {
+    Self {
+      steps: if pal {[&STEPS_4_PAL, &STEPS_5_PAL]} else {[&STEPS_4_NTSC, &STEPS_5_NTSC]},
+      cycle: 0,
+      five_step: false,
+      irq_inhibit: false,
+      irq: false,
+      odd_cycle: false,
+      pending_write: None,
+    }
+  }
+
+  /// The mode change and sequencer reset happen 3 or 4 CPU cycles after the write,
+  /// depending on whether it occurs during an APU cycle, they are scheduled by the `Bus`.
+  pub fn write(&mut self, value: u8) {
+    self.irq_inhibit = value & 0b0100_0000 != 0;
+    if self.irq_inhibit {
+      self.irq = false;
+    }
+    self.pending_write = Some((if self.odd_cycle {4} else {3}, value));
+  }
+
+  /// Mode change and sequencer reset, once the write delay has elapsed
+  pub fn apply_write(&mut self, value: u8) -> FrameClock {
+    self.five_step = value & 0b1000_0000 != 0;
+    self.cycle = 0;
+    // the 5-step mode clocks the units right away
+    FrameClock {
+      quarter: self.five_step,
+      half: self.five_step,
+    }
+  }
+
+  pub fn tick(&mut self) -> FrameClock {
+    let mut clock = FrameClock::default();
+    self.odd_cycle = !self.odd_cycle;
+
+    self.cycle += 1;
+    let steps = self.steps[self.five_step as usize];
+    match steps.iter().position(|s| *s == self.cycle) {
+      Some(0) | Some(2) => clock.quarter = true,
+      Some(1) => {
+        clock.quarter = true;
+        clock.half = true;
+      },
+      Some(3) => self.set_irq(),
+      Some(4) => {
+        clock.quarter = true;
+        clock.half = true;
+        self.set_irq();
+      },
+      Some(_) => {
+        self.set_irq();
+        self.cycle = 0;
+      },
+      None => (),
+    }
+    clock
+  }
+
+  /// Only raised in 4-step mode
+  fn set_irq(&mut self) {
+    if !self.five_step && !self.irq_inhibit {
+      self.irq = true;
+    }
+  }
+}
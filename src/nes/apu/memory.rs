@@ -1,6 +1,8 @@
 use std::fmt;
 
 use crate::nes::memory::{MemRead, MemWrite};
+use crate::nes::apu::frame_counter::{FrameCounter, FrameClock};
+use crate::nes::apu::vgm::VgmLogger;
 
 #[derive(Debug)]
 pub struct APUMemory {
@@ -11,9 +13,27 @@ pub struct APUMemory {
   pub dmc_channel: [u8; 4],
 
   pub status: u8,
-  pub frame_counter: u8,
-
-  write_fc_counter: usize,
+  pub frame_counter: FrameCounter,
+  /// Frame clocks since the last APU cycle
+  pub frame_clock: FrameClock,
+  /// Channels whose length counter is non zero, read back from $4015
+  pub length_status: u8,
+  /// Set by writes to the channels' 4th register, one bit per channel like $4015
+  pub length_reload: u8,
+  /// Set by $4001/$4005 writes
+  pub sweep_reload: u8,
+  /// CPU cycles, timestamps of the logged writes
+  pub cycles: u64,
+  pub vgm: Option<VgmLogger>,
+  /// Sample address the DMC wants fetched, serviced by the `Bus` DMA
+  pub dmc_dma_addr: Option<usize>,
+  /// Sample byte fetched by the DMA, consumed by the DMC
+  pub dmc_dma_data: Option<u8>,
+  pub dmc_irq: bool,
+  /// Set by $4011 writes, loaded in the DMC output level
+  pub dmc_direct_load: Option<u8>,
+  /// Set by $4015 writes, the DMC starts or stops its sample
+  pub dmc_enable_write: bool,
 }
 
 impl fmt::Display for APUMemory {
@@ -31,20 +51,103 @@ impl APUMemory {
       noise_channel: [0; 3],
       dmc_channel: [0; 4],
       status: 0,
-      frame_counter: 0,
-      write_fc_counter: 0,
+      frame_counter: FrameCounter::new(false),
+      frame_clock: FrameClock::default(),
+      length_status: 0,
+      length_reload: 0,
+      sweep_reload: 0,
+      cycles: 0,
+      vgm: None,
+      dmc_dma_addr: None,
+      dmc_dma_data: None,
+      dmc_irq: false,
+      dmc_direct_load: None,
+      dmc_enable_write: false,
     }
   }
 
-  pub(super) fn tick(&mut self) -> bool {
-    self.frame_counter.wrapping_add(1);
-    if self.write_fc_counter > 0 {
-      self.write_fc_counter -= 1;
-      if self.write_fc_counter == 0 {
-        self.frame_counter = 0;
+  /// Clocks the frame sequencer, its clocks are kept until the next APU cycle.
+  pub fn cpu_clock(&mut self) {
+    self.cycles = self.cycles.wrapping_add(1);
+    let clock = self.frame_counter.tick();
+    self.frame_clock.quarter |= clock.quarter;
+    self.frame_clock.half |= clock.half;
+  }
+
+  pub fn frame_counter_write(&mut self, value: u8) {
+    let clock = self.frame_counter.apply_write(value);
+    self.frame_clock.quarter |= clock.quarter;
+    self.frame_clock.half |= clock.half;
+  }
+
+  pub(super) fn clear_frame_clock(&mut self) {
+    self.frame_clock = FrameClock::default();
+  }
+
+  /// PAL frame sequencer timings
+  pub fn set_pal(&mut self, pal: bool) {
+    self.frame_counter = FrameCounter::new(pal);
+  }
+
+  /// Starts a VGM log with the current register values, `cpu_freq` is the clock of the region
+  pub fn start_vgm_log(&mut self, cpu_freq: f64) {
+    let mut vgm = VgmLogger::new(self.cycles, cpu_freq);
+    let registers = [
+      (0x4000, &self.pulse1_channel[..]),
+      (0x4004, &self.pulse2_channel[..]),
+      (0x4010, &self.dmc_channel[..]),
+    ];
+    for (first_addr, channel_reg) in registers.iter() {
+      for (i, value) in channel_reg.iter().enumerate() {
+        vgm.write(self.cycles, first_addr + i, *value);
       }
     }
-    false
+    for (addr, value) in [0x4008, 0x400A, 0x400B].iter().zip(self.triangle_channel.iter()) {
+      vgm.write(self.cycles, *addr, *value);
+    }
+    for (addr, value) in [0x400C, 0x400E, 0x400F].iter().zip(self.noise_channel.iter()) {
+      vgm.write(self.cycles, *addr, *value);
+    }
+    vgm.write(self.cycles, 0x4015, self.status);
+    self.vgm = Some(vgm);
+  }
+
+  /// Returns the VGM file of the log
+  pub fn stop_vgm_log(&mut self) -> Option<Vec<u8>> {
+    self.vgm.take().map(|vgm| vgm.to_vgm(self.cycles))
+  }
+
+  /// Expansion audio writes go to the mapper, they are only logged here
+  pub fn log_write(&mut self, addr: usize, value: u8) {
+    if let Some(vgm) = &mut self.vgm {
+      vgm.write(self.cycles, addr, value);
+    }
+  }
+
+  pub fn log_dmc_fetch(&mut self, addr: usize, value: u8) {
+    if let Some(vgm) = &mut self.vgm {
+      vgm.dmc_fetch(self.cycles, addr, value);
+    }
+  }
+
+  pub fn irq(&self) -> bool {
+    self.frame_counter.irq || self.dmc_irq
+  }
+
+  pub fn set_length_status(&mut self, channel_bit: u8, active: bool) {
+    if active {
+      self.length_status |= channel_bit;
+    }
+    else {
+      self.length_status &= !channel_bit;
+    }
+  }
+
+  /// Returns whether the channel's 4th register was written, and clears the flag
+  pub fn take_length_reload(&mut self, channel_bit: u8) -> bool {
+    let reload = self.length_reload & channel_bit != 0;
+    self.length_reload &= !channel_bit;
+    reload
   }
 
   pub fn get_channel_reg(&self, addr: usize) -> &[u8]{
@@ -73,7 +176,11 @@ impl APUMemory {
 impl MemRead for APUMemory {
   fn read(&mut self, addr: usize) -> u8 {
     if addr == 0x4015 {
-      self.status
+      let frame_irq = if self.frame_counter.irq {0b0100_0000} else {0};
+      let dmc_irq = if self.dmc_irq {0b1000_0000} else {0};
+      // reading clears the frame IRQ
+      self.frame_counter.irq = false;
+      (self.length_status & 0b0001_1111) | frame_irq | dmc_irq
     }
     else {
       0
@@ -83,36 +190,64 @@ impl MemRead for APUMemory {
 
 impl MemWrite for APUMemory {
   fn write(&mut self, addr: usize, value: u8) {
+    self.log_write(addr, value);
     let addr = addr as u16;
     match addr {
       0x4000 => self.pulse1_channel[0] = value,
-      0x4001 => self.pulse1_channel[1] = value,
+      0x4001 => {
+        self.pulse1_channel[1] = value;
+        self.sweep_reload |= 0b0001;
+      },
       0x4002 => self.pulse1_channel[2] = value,
-      0x4003 => self.pulse1_channel[3] = value,
+      0x4003 => {
+        self.pulse1_channel[3] = value;
+        self.length_reload |= 0b0001;
+      },
 
       0x4004 => self.pulse2_channel[0] = value,
-      0x4005 => self.pulse2_channel[1] = value,
+      0x4005 => {
+        self.pulse2_channel[1] = value;
+        self.sweep_reload |= 0b0010;
+      },
       0x4006 => self.pulse2_channel[2] = value,
-      0x4007 => self.pulse2_channel[3] = value,
+      0x4007 => {
+        self.pulse2_channel[3] = value;
+        self.length_reload |= 0b0010;
+      },
 
       0x4008 => self.triangle_channel[0] = value,
       0x400A => self.triangle_channel[1] = value,
-      0x400B => self.triangle_channel[2] = value,
+      0x400B => {
+        self.triangle_channel[2] = value;
+        self.length_reload |= 0b0100;
+      },
 
       0x400C => self.noise_channel[0] = value & 0b0011_1111,
       0x400E => self.noise_channel[1] = value & 0b1000_1111,
-      0x400F => self.noise_channel[2] = value & 0b1111_1000,
+      0x400F => {
+        self.noise_channel[2] = value & 0b1111_1000;
+        self.length_reload |= 0b1000;
+      },
 
-      0x4010 => self.dmc_channel[0] = value & 0b1100_1111,
-      0x4011 => self.dmc_channel[1] = value & 0b0111_1111,
+      0x4010 => {
+        self.dmc_channel[0] = value & 0b1100_1111;
+        if value & 0b1000_0000 == 0 {
+          self.dmc_irq = false;
+        }
+      },
+      0x4011 => {
+        self.dmc_channel[1] = value & 0b0111_1111;
+        self.dmc_direct_load = Some(value & 0b0111_1111);
+      },
       0x4012 => self.dmc_channel[2] = value,
       0x4013 => self.dmc_channel[3] = value,
 
-      0x4015 => self.status = value & 0b0001_1111,
-      0x4017 => {
-        self.frame_counter = value & 0b1100_0000;
-        self.write_fc_counter = 2;
+      0x4015 => {
+        self.status = value & 0b0001_1111;
+        self.dmc_enable_write = true;
+        self.dmc_irq = false;
       },
+      0x4017 => self.frame_counter.write(value),
       _ => (),
     }
   }
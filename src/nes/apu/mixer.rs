@@ -1,41 +1,133 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+
 use cpal::traits::{DeviceTrait, StreamTrait};
 
-static mut stream_data: Vec<f32> = vec![];
+/// Buffered audio, in seconds
+const BUFFER_LENGTH: f32 = 0.1;
+/// Buffer fill the emulation is paced to
+const TARGET_FILL: f32 = 0.5;
+/// Maximum resampling ratio adjustment of the dynamic rate control
+const MAX_RATE_DELTA: f64 = 0.005;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AudioStats {
+  pub sample_rate: u32,
+  /// Buffer fill, 0 to 1
+  pub fill: f32,
+  pub rate_ratio: f64,
+  pub underruns: u32,
+  pub overruns: u32,
+}
 
 pub struct Mixer {
-  device: cpal::Device,
+  /// `None` for a mixer without output
+  device: Option<cpal::Device>,
   config: cpal::StreamConfig,
   sample_format: cpal::SampleFormat,
   stream: Option<cpal::Stream>,
   mute: bool,
   volume: f32,
+  buffer: Arc<Mutex<VecDeque<f32>>>,
+  capacity: usize,
+  underruns: Arc<AtomicU32>,
+  overruns: u32,
 }
 
 impl Mixer {
   pub fn new(device: cpal::Device, config: cpal::SupportedStreamConfig) -> Self {
-    let mut mixer = Self {
-      device,
+    let capacity = ((config.sample_rate().0 as f32) * BUFFER_LENGTH) as usize;
+    Self {
+      device: Some(device),
       sample_format: config.sample_format(),
       config: config.into(),
       stream: None,
       mute: false,
       volume: 0.3,
-    };
-    mixer.config.sample_rate = cpal::SampleRate(44100);
-    mixer
+      buffer: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+      capacity,
+      underruns: Arc::new(AtomicU32::new(0)),
+      overruns: 0,
+    }
   }
 
+  /// Mixer without output device, it never plays and drops the samples
+  #[cfg(test)]
+  pub fn silent(sample_rate: u32) -> Self {
+    Self {
+      device: None,
+      sample_format: cpal::SampleFormat::F32,
+      config: cpal::StreamConfig {
+        channels: 1,
+        sample_rate: cpal::SampleRate(sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+      },
+      stream: None,
+      mute: true,
+      volume: 0.3,
+      buffer: Arc::new(Mutex::new(VecDeque::new())),
+      capacity: 0,
+      underruns: Arc::new(AtomicU32::new(0)),
+      overruns: 0,
+    }
+  }
+
+  /// The rate negotiated with the output device
+  pub fn sample_rate(&self) -> u32 {
+    self.config.sample_rate.0
+  }
+
+  /// Samples are dropped while no stream is playing
   pub fn add_to_stream(&mut self, data: f32) {
-    unsafe {
-      stream_data.push(data * self.volume);
+    if self.stream.is_none() {
+      return;
+    }
+    let mut buffer = self.buffer.lock().unwrap();
+    if buffer.len() >= self.capacity {
+      self.overruns += 1;
+      buffer.pop_front();
+    }
+    buffer.push_back(data * self.volume);
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.stream.is_some()
+  }
+
+  pub fn fill(&self) -> f32 {
+    self.buffer.lock().unwrap().len() as f32 / self.capacity as f32
+  }
+
+  /// Output rate adjustment keeping the buffer around its target fill:
+  /// slightly fewer samples are produced when it is fuller, more when it drains.
+  pub fn rate_ratio(&self) -> f64 {
+    if !self.is_playing() {
+      return 1.0;
+    }
+    let error = ((TARGET_FILL - self.fill()) / TARGET_FILL).clamp(-1.0, 1.0);
+    1.0 + MAX_RATE_DELTA * (error as f64)
+  }
+
+  /// Whether the emulation should wait for the audio to drain before the next frame
+  pub fn ahead(&self) -> bool {
+    self.is_playing() && self.fill() > TARGET_FILL
+  }
+
+  pub fn stats(&self) -> AudioStats {
+    AudioStats {
+      sample_rate: self.sample_rate(),
+      fill: self.fill(),
+      rate_ratio: self.rate_ratio(),
+      underruns: self.underruns.load(Ordering::Relaxed),
+      overruns: self.overruns,
     }
   }
 
   pub fn set_mute(&mut self, m: bool) {
+    self.mute = m;
     if !m {
-      unsafe {
-        stream_data.clear();
-      }
+      self.buffer.lock().unwrap().clear();
       match self.sample_format {
         cpal::SampleFormat::F32 => self.run::<f32>(),
         cpal::SampleFormat::I16 => self.run::<i16>(),
@@ -48,28 +140,27 @@ impl Mixer {
   }
 
   fn run<T: cpal::Sample>(&mut self) {
-    let sample_rate = self.config.sample_rate.0 as f32;
+    let device = match &self.device {
+      Some(device) => device,
+      None => return,
+    };
     let channels = self.config.channels as usize;
-
-    let sample_clock = 0f32;
     let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
 
-    unsafe {
-      let mut next_value = move || {
-        match stream_data.pop() {
-          Some(f) => f,
-          None => 0f32,
+    let buffer = self.buffer.clone();
+    let underruns = self.underruns.clone();
+    self.stream = Some(device.build_output_stream(
+      &self.config,
+      move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        let mut buffer = buffer.lock().unwrap();
+        if buffer.len() < data.len() / channels {
+          underruns.fetch_add(1, Ordering::Relaxed);
         }
-      };
-      self.stream = Some(self.device.build_output_stream(
-        &self.config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-          Self::write_data(data, channels, &mut next_value)
-        },
-        err_fn,
-      ).unwrap());
-      if let Some(stream) = &self.stream {stream.play().unwrap();}
-    }
+        Self::write_data(data, channels, &mut || buffer.pop_front().unwrap_or(0f32))
+      },
+      err_fn,
+    ).unwrap());
+    if let Some(stream) = &self.stream {stream.play().unwrap();}
   }
 
   fn write_data<T: cpal::Sample>(output: &mut [T], channels: usize, next_sample: &mut dyn FnMut() -> f32) {
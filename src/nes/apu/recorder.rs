@@ -0,0 +1,84 @@
+use std::io;
+
+use crate::nes::apu::{
+  blip::BlipBuffer,
+  filter::FilterChain,
+  wav::{WavFormat, WavWriter},
+};
+
+const STEM_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+
+/// A single output resampled on its own, at a fixed ratio
+struct Stem {
+  blip: BlipBuffer,
+  amplitude: f32,
+  filters: FilterChain,
+  samples: Vec<f32>,
+  wav: WavWriter,
+}
+
+impl Stem {
+  fn create(filename: &str, clock_rate: f64, sample_rate: u32, format: WavFormat) -> io::Result<Self> {
+    Ok(Self {
+      blip: BlipBuffer::new(clock_rate, sample_rate as f64),
+      amplitude: 0.0,
+      filters: FilterChain::new(sample_rate as f32),
+      samples: Vec::new(),
+      wav: WavWriter::create(filename, sample_rate, format)?,
+    })
+  }
+
+  fn record(&mut self, amplitude: f32, clocks: u32) -> io::Result<()> {
+    self.blip.add_delta(amplitude - self.amplitude);
+    self.amplitude = amplitude;
+    self.blip.advance(clocks);
+    self.blip.read_samples(&mut self.samples);
+    for sample in self.samples.drain(..) {
+      self.wav.write_sample(self.filters.process(sample))?;
+    }
+    Ok(())
+  }
+}
+
+/// Records the mixed output, and optionally each channel to its own file.
+/// It has its own resampling, unaffected by the audio pacing, so that the pitch and tempo are exact.
+pub struct Recorder {
+  mix: Stem,
+  stems: Vec<Stem>,
+}
+
+impl Recorder {
+  /// Stems are written next to `filename`, suffixed by the channel name.
+  pub fn create(filename: &str, clock_rate: f64, sample_rate: u32, format: WavFormat, stems: bool) -> io::Result<Self> {
+    let mut recorder = Self {
+      mix: Stem::create(filename, clock_rate, sample_rate, format)?,
+      stems: Vec::new(),
+    };
+    if stems {
+      let base = filename.strip_suffix(".wav").unwrap_or(filename);
+      for name in STEM_NAMES.iter() {
+        let stem_filename = format!("{}.{}.wav", base, name);
+        recorder.stems.push(Stem::create(&stem_filename, clock_rate, sample_rate, format)?);
+      }
+    }
+    Ok(recorder)
+  }
+
+  /// `mix` is the mixed output and `amplitudes` the channel outputs after the mixer,
+  /// `clocks` the time since the last call
+  pub fn record(&mut self, mix: f32, amplitudes: &[f32; 5], clocks: u32) -> io::Result<()> {
+    self.mix.record(mix, clocks)?;
+    for (stem, amplitude) in self.stems.iter_mut().zip(amplitudes.iter()) {
+      stem.record(*amplitude, clocks)?;
+    }
+    Ok(())
+  }
+
+  pub fn finish(&mut self) -> io::Result<()> {
+    self.mix.wav.finish()?;
+    for stem in self.stems.iter_mut() {
+      stem.wav.finish()?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+/// Waveform points kept per channel
+pub const SCOPE_POINTS: usize = 256;
+/// APU cycles per point, the history spans about 3 frames
+const TICKS_PER_POINT: u32 = 174;
+/// Output samples kept for the spectrum
+const SPECTRUM_SAMPLES: usize = 2048;
+const SPECTRUM_MIN_FREQ: f32 = 40.0;
+const SPECTRUM_MAX_FREQ: f32 = 12_000.0;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+/// Waveform lanes: the 5 APU channels, then the mixed output
+pub const SCOPE_LANES: usize = 6;
+
+/// Min and max level of a lane over a point, 0.0 to 1.0
+pub type ScopePoint = (f32, f32);
+
+/// Recent history of the channel outputs, for the oscilloscope and spectrum view.
+pub struct Scope {
+  lanes: [VecDeque<ScopePoint>; SCOPE_LANES],
+  current: [ScopePoint; SCOPE_LANES],
+  ticks: u32,
+  samples: VecDeque<f32>,
+  sample_rate: f32,
+}
+
+/// Note name and octave of `freq`, A4 being 440Hz
+pub fn note_name(freq: f64) -> String {
+  let note = (12.0 * (freq / 440.0).log2() + 69.0).round() as i32;
+  format!("{}{}", NOTE_NAMES[note.rem_euclid(12) as usize], note.div_euclid(12) - 1)
+}
+
+impl Scope {
+  pub fn new(sample_rate: u32) -> Self {
+    Self {
+      lanes: Default::default(),
+      current: [(f32::MAX, f32::MIN); SCOPE_LANES],
+      ticks: 0,
+      samples: VecDeque::with_capacity(SPECTRUM_SAMPLES),
+      sample_rate: sample_rate as f32,
+    }
+  }
+
+  /// `levels` are the channel outputs and the mixed output, normalized to 0.0..=1.0
+  pub fn push_levels(&mut self, levels: &[f32; SCOPE_LANES]) {
+    for (current, level) in self.current.iter_mut().zip(levels.iter()) {
+      current.0 = current.0.min(*level);
+      current.1 = current.1.max(*level);
+    }
+    self.ticks += 1;
+    if self.ticks == TICKS_PER_POINT {
+      self.ticks = 0;
+      for (lane, current) in self.lanes.iter_mut().zip(self.current.iter_mut()) {
+        if lane.len() == SCOPE_POINTS {
+          lane.pop_front();
+        }
+        lane.push_back(*current);
+        *current = (f32::MAX, f32::MIN);
+      }
+    }
+  }
+
+  pub fn push_sample(&mut self, sample: f32) {
+    if self.samples.len() == SPECTRUM_SAMPLES {
+      self.samples.pop_front();
+    }
+    self.samples.push_back(sample);
+  }
+
+  /// Oldest point first
+  pub fn lane(&self, lane: usize) -> &VecDeque<ScopePoint> {
+    &self.lanes[lane]
+  }
+
+  /// Magnitudes of `bands` log spaced bands of the output, Hann windowed Goertzel filters.
+  pub fn spectrum(&self, bands: usize) -> Vec<f32> {
+    let n = self.samples.len();
+    if n == 0 {
+      return vec![0.0; bands];
+    }
+    let max_freq = SPECTRUM_MAX_FREQ.min(self.sample_rate / 2.0);
+    (0..bands).map(|band| {
+      let freq = SPECTRUM_MIN_FREQ * (max_freq / SPECTRUM_MIN_FREQ).powf(band as f32 / bands as f32);
+      let coeff = 2.0 * (2.0 * PI * freq / self.sample_rate).cos();
+      let (mut s1, mut s2) = (0f32, 0f32);
+      for (i, sample) in self.samples.iter().enumerate() {
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / n as f32).cos();
+        let s = sample * window + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+      }
+      (s1 * s1 + s2 * s2 - coeff * s1 * s2).max(0.0).sqrt() / n as f32
+    }).collect()
+  }
+}
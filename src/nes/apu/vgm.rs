@@ -0,0 +1,192 @@
+const VGM_VERSION: u32 = 0x0000_0161;
+const HEADER_SIZE: usize = 0x100;
+const VGM_SAMPLE_RATE: u64 = 44100;
+const FDS_FLAG: u32 = 0x8000_0000;
+
+const CMD_NES_APU_WRITE: u8 = 0xB4;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_735: u8 = 0x62;
+const CMD_WAIT_882: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_DATA_BLOCK: u8 = 0x67;
+const CMD_END: u8 = 0x66;
+const BLOCK_NES_APU_RAM: u8 = 0xC2;
+
+/// DMC samples are fetched from $8000-$FFFF
+const SAMPLE_RAM_START: usize = 0x8000;
+
+#[derive(Debug, Clone)]
+enum VgmEvent {
+  Write {cycle: u64, reg: u8, value: u8},
+  /// DMC sample data, written to the player's memory before it is fetched
+  Ram {cycle: u64, addr: usize, data: Vec<u8>},
+}
+
+/// Logs the APU and FDS register writes with their CPU cycle, exported as a VGM file.
+#[derive(Debug, Clone)]
+pub struct VgmLogger {
+  start_cycle: u64,
+  /// CPU clock of the region, in Hz
+  cpu_freq: f64,
+  events: Vec<VgmEvent>,
+  fds: bool,
+  /// Sample bytes already sent to the player
+  sample_ram: Vec<Option<u8>>,
+}
+
+/// VGM register of the NES APU chip: $4000-$401F, $4080-$409E, $4023 and the FDS wave RAM at $4040-$407F
+fn vgm_register(addr: usize) -> Option<u8> {
+  match addr {
+    0x4000..=0x401F => Some((addr - 0x4000) as u8),
+    0x4080..=0x409E => Some((addr - 0x4080 + 0x20) as u8),
+    0x4023 => Some(0x3F),
+    0x4040..=0x407F => Some((addr - 0x4040 + 0x40) as u8),
+    _ => None,
+  }
+}
+
+fn set_u32(out: &mut [u8], pos: usize, value: u32) {
+  out[pos..(pos + 4)].copy_from_slice(&value.to_le_bytes());
+}
+
+impl VgmLogger {
+  pub fn new(start_cycle: u64, cpu_freq: f64) -> Self {
+    Self {
+      start_cycle,
+      cpu_freq,
+      events: Vec::new(),
+      fds: false,
+      sample_ram: vec![None; 0x10000 - SAMPLE_RAM_START],
+    }
+  }
+
+  pub fn write(&mut self, cycle: u64, addr: usize, value: u8) {
+    if let Some(reg) = vgm_register(addr) {
+      self.fds |= reg >= 0x20;
+      self.events.push(VgmEvent::Write {cycle: cycle - self.start_cycle, reg, value});
+    }
+  }
+
+  /// Sample bytes the player doesn't have yet are logged, contiguous bytes share a block.
+  pub fn dmc_fetch(&mut self, cycle: u64, addr: usize, value: u8) {
+    if addr < SAMPLE_RAM_START || self.sample_ram[addr - SAMPLE_RAM_START] == Some(value) {
+      return;
+    }
+    self.sample_ram[addr - SAMPLE_RAM_START] = Some(value);
+    if let Some(VgmEvent::Ram {addr: block_addr, data, ..}) = self.events.last_mut() {
+      if *block_addr + data.len() == addr {
+        data.push(value);
+        return;
+      }
+    }
+    self.events.push(VgmEvent::Ram {cycle: cycle - self.start_cycle, addr, data: vec![value]});
+  }
+
+  fn write_wait(out: &mut Vec<u8>, mut samples: u64) {
+    while samples > 0 {
+      match samples {
+        735 => {out.push(CMD_WAIT_735); samples = 0;},
+        882 => {out.push(CMD_WAIT_882); samples = 0;},
+        1..=16 => {out.push(CMD_WAIT_SHORT + (samples - 1) as u8); samples = 0;},
+        _ => {
+          let wait = samples.min(0xFFFF);
+          out.push(CMD_WAIT);
+          out.extend_from_slice(&(wait as u16).to_le_bytes());
+          samples -= wait;
+        },
+      }
+    }
+  }
+
+  /// `end_cycle` sets the length of the log
+  pub fn to_vgm(&self, end_cycle: u64) -> Vec<u8> {
+    let to_samples = |cycle: u64| (cycle as f64 * VGM_SAMPLE_RATE as f64 / self.cpu_freq) as u64;
+    let mut out = vec![0u8; HEADER_SIZE];
+    let mut sample = 0;
+    for event in &self.events {
+      let cycle = match event {
+        VgmEvent::Write {cycle, ..} | VgmEvent::Ram {cycle, ..} => *cycle,
+      };
+      Self::write_wait(&mut out, to_samples(cycle) - sample);
+      sample = to_samples(cycle);
+      match event {
+        VgmEvent::Write {reg, value, ..} => out.extend_from_slice(&[CMD_NES_APU_WRITE, *reg, *value]),
+        VgmEvent::Ram {addr, data, ..} => {
+          out.extend_from_slice(&[CMD_DATA_BLOCK, CMD_END, BLOCK_NES_APU_RAM]);
+          out.extend_from_slice(&((data.len() + 2) as u32).to_le_bytes());
+          out.extend_from_slice(&(*addr as u16).to_le_bytes());
+          out.extend_from_slice(data);
+        },
+      }
+    }
+    let total_samples = to_samples(end_cycle.saturating_sub(self.start_cycle)).max(sample);
+    Self::write_wait(&mut out, total_samples - sample);
+    out.push(CMD_END);
+
+    out[0..4].copy_from_slice(b"Vgm ");
+    let len = out.len();
+    set_u32(&mut out, 0x04, (len - 4) as u32);
+    set_u32(&mut out, 0x08, VGM_VERSION);
+    set_u32(&mut out, 0x18, total_samples as u32);
+    // data offset, relative to its own position
+    set_u32(&mut out, 0x34, (HEADER_SIZE - 0x34) as u32);
+    set_u32(&mut out, 0x84, self.cpu_freq.round() as u32 | if self.fds {FDS_FLAG} else {0});
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::nes::region::Region;
+
+  fn u32_at(vgm: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([vgm[pos], vgm[pos + 1], vgm[pos + 2], vgm[pos + 3]])
+  }
+
+  #[test]
+  fn commands_and_header() {
+    let cpu_freq = Region::Ntsc.cpu_freq();
+    // first cycle of the 735th and 740th samples
+    let cycle = |samples: f64| 100 + (samples * cpu_freq / VGM_SAMPLE_RATE as f64).ceil() as u64;
+    let mut vgm = VgmLogger::new(100, cpu_freq);
+    vgm.write(100, 0x4000, 0x3F);
+    vgm.write(cycle(735.0), 0x4015, 0x0F);
+    vgm.write(cycle(735.0), 0x4083, 0x80);
+    // not a sound register
+    vgm.write(cycle(735.0), 0x4030, 0x01);
+    vgm.dmc_fetch(cycle(735.0), 0xC000, 0x01);
+    vgm.dmc_fetch(cycle(735.0), 0xC001, 0x02);
+    // already sent
+    vgm.dmc_fetch(cycle(735.0), 0xC000, 0x01);
+    let out = vgm.to_vgm(cycle(740.0));
+
+    assert_eq!(out[HEADER_SIZE..], [
+      CMD_NES_APU_WRITE, 0x00, 0x3F,
+      CMD_WAIT_735,
+      CMD_NES_APU_WRITE, 0x15, 0x0F,
+      CMD_NES_APU_WRITE, 0x23, 0x80,
+      CMD_DATA_BLOCK, CMD_END, BLOCK_NES_APU_RAM, 4, 0, 0, 0, 0x00, 0xC0, 0x01, 0x02,
+      CMD_WAIT_SHORT + 4,
+      CMD_END,
+    ]);
+    assert_eq!(&out[..4], b"Vgm ");
+    assert_eq!(u32_at(&out, 0x04) as usize, out.len() - 4);
+    assert_eq!(u32_at(&out, 0x08), VGM_VERSION);
+    assert_eq!(u32_at(&out, 0x18), 740);
+    assert_eq!(u32_at(&out, 0x34) as usize + 0x34, HEADER_SIZE);
+    assert_eq!(u32_at(&out, 0x84), 1_789_773 | FDS_FLAG);
+  }
+
+  #[test]
+  fn region_clock() {
+    let cpu_freq = Region::Pal.cpu_freq();
+    let mut vgm = VgmLogger::new(0, cpu_freq);
+    vgm.write(0, 0x4017, 0x40);
+    // one second, a single 44100 samples wait
+    let out = vgm.to_vgm(cpu_freq.ceil() as u64);
+    assert_eq!(u32_at(&out, 0x84), cpu_freq.round() as u32);
+    assert_eq!(u32_at(&out, 0x18), 44100);
+    assert_eq!(out[HEADER_SIZE..], [CMD_NES_APU_WRITE, 0x17, 0x40, CMD_WAIT, 0x44, 0xAC, CMD_END]);
+  }
+}
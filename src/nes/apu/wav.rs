@@ -0,0 +1,95 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+const HEADER_SIZE: u32 = 44;
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WavFormat {
+  Pcm16,
+  Float32,
+}
+
+impl WavFormat {
+  fn bytes_per_sample(&self) -> u16 {
+    match self {
+      WavFormat::Pcm16 => 2,
+      WavFormat::Float32 => 4,
+    }
+  }
+}
+
+/// Mono WAV file, the sizes in the header are written by `finish`.
+pub struct WavWriter {
+  file: BufWriter<File>,
+  format: WavFormat,
+  sample_rate: u32,
+  data_len: u32,
+  finished: bool,
+}
+
+impl WavWriter {
+  pub fn create(filename: &str, sample_rate: u32, format: WavFormat) -> io::Result<Self> {
+    let mut wav = Self {
+      file: BufWriter::new(File::create(filename)?),
+      format,
+      sample_rate,
+      data_len: 0,
+      finished: false,
+    };
+    wav.write_header()?;
+    Ok(wav)
+  }
+
+  fn write_header(&mut self) -> io::Result<()> {
+    let bytes_per_sample = self.format.bytes_per_sample();
+    let format_tag = if self.format == WavFormat::Float32 {FORMAT_FLOAT} else {FORMAT_PCM};
+    let f = &mut self.file;
+    f.write_all(b"RIFF")?;
+    f.write_all(&(HEADER_SIZE - 8 + self.data_len).to_le_bytes())?;
+    f.write_all(b"WAVE")?;
+    f.write_all(b"fmt ")?;
+    f.write_all(&16u32.to_le_bytes())?;
+    f.write_all(&format_tag.to_le_bytes())?;
+    // mono
+    f.write_all(&1u16.to_le_bytes())?;
+    f.write_all(&self.sample_rate.to_le_bytes())?;
+    f.write_all(&(self.sample_rate * bytes_per_sample as u32).to_le_bytes())?;
+    f.write_all(&bytes_per_sample.to_le_bytes())?;
+    f.write_all(&(bytes_per_sample * 8).to_le_bytes())?;
+    f.write_all(b"data")?;
+    f.write_all(&self.data_len.to_le_bytes())
+  }
+
+  /// `sample` is clamped to -1.0..1.0 in 16-bit files
+  pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+    match self.format {
+      WavFormat::Pcm16 => {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.file.write_all(&value.to_le_bytes())?;
+      },
+      WavFormat::Float32 => self.file.write_all(&sample.to_le_bytes())?,
+    }
+    self.data_len += self.format.bytes_per_sample() as u32;
+    Ok(())
+  }
+
+  pub fn finish(&mut self) -> io::Result<()> {
+    if self.finished {
+      return Ok(());
+    }
+    self.finished = true;
+    self.file.seek(SeekFrom::Start(0))?;
+    self.write_header()?;
+    self.file.flush()
+  }
+}
+
+impl Drop for WavWriter {
+  fn drop(&mut self) {
+    if let Err(e) = self.finish() {
+      eprintln!("Can't finish WAV file: {}", e);
+    }
+  }
+}
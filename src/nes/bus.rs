@@ -1,12 +1,16 @@
+mod cpu_map;
+
 use crate::nes::{
   memory::{MemRead, MemWrite, Memory},
   mapper::{self, Mapper, MapperType},
-  ppu::memory::{PPUMemory, OAMDMA_CPU_ADDR},
+  ppu::memory::PPUMemory,
   apu::memory::{APUMemory},
   apu::mixer::{Mixer},
   controller::{Controller},
   save_state::SaveState,
+  clock::{Scheduler, Event},
 };
+use cpu_map::{CpuDevice, cpu_read_map, cpu_write_map};
 
 pub struct Bus {
   pub(super) wram: Memory,
@@ -14,8 +18,12 @@ pub struct Bus {
   pub ppu_mem: PPUMemory,
   pub apu_mem: APUMemory,
   oam_dma: (bool, u8, u8),
+  last_read_addr: usize,
+  /// Last value on the CPU data bus, read back from the unmapped addresses
+  open_bus: u8,
   pub(super) input: Box<dyn Controller>,
   pub mixer: Mixer,
+  pub scheduler: Scheduler,
   //ppu: PPU,
   //apu: APU,
   //input: Input,
@@ -23,15 +31,18 @@ pub struct Bus {
 }
 
 impl Bus {
-  pub fn new(input: Box<dyn Controller>, mixer: Mixer) -> Self {
+  pub fn new(input: Box<dyn Controller>, mixer: Mixer, scheduler: Scheduler) -> Self {
     Self {
       wram: Memory::ram(0x0800),
       ppu_mem: PPUMemory::new(),
       apu_mem: APUMemory::new(),
       mapper: Box::new(mapper::null()),
       oam_dma: (false, 0, 0),
+      last_read_addr: 0,
+      open_bus: 0,
       input,
       mixer,
+      scheduler,
     }
   }
 
@@ -48,6 +59,37 @@ impl Bus {
     println!("{}", self.ppu_mem);
   }
 
+  pub fn cpu_clock(&mut self) {
+    self.mapper.cpu_clock();
+    self.apu_mem.cpu_clock();
+  }
+
+  pub fn irq_pending(&mut self) -> bool {
+    self.mapper.irq_pending() || self.apu_mem.irq()
+  }
+
+  pub fn dmc_dma_pending(&self) -> bool {
+    self.apu_mem.dmc_dma_addr.is_some()
+  }
+
+  /// Fetches the DMC sample byte, returns the number of CPU cycles stolen.
+  pub fn dmc_dma(&mut self) -> u32 {
+    let addr = match self.apu_mem.dmc_dma_addr.take() {
+      Some(addr) => addr,
+      None => return 0,
+    };
+    let last_read_addr = self.last_read_addr;
+    // the halted CPU repeats its last read, which clocks the controller shift register again
+    if !self.oam_dma.0 && (last_read_addr == 0x4016 || last_read_addr == 0x4017) {
+      self.read(last_read_addr);
+    }
+    let value = self.read(addr);
+    self.last_read_addr = last_read_addr;
+    self.apu_mem.dmc_dma_data = Some(value);
+    self.apu_mem.log_dmc_fetch(addr, value);
+    if self.oam_dma.0 {2} else {4}
+  }
+
   pub fn get_oam_dma_state(&self) -> bool {
     self.oam_dma.0
   }
@@ -83,28 +125,48 @@ impl Bus {
 
 impl MemRead for Bus {
   fn read(&mut self, addr: usize) -> u8 {
-    match addr {
-      0x0000..=0x0800 => self.wram.read(addr),
-      (0x2000..=0x2007) | 0x4014 => self.ppu_mem.read(&mut self.mapper, addr),
-      0x4016 | 0x4017 => self.input.read(addr),
-      0x4000..=0x4017 => self.apu_mem.read(addr),
-      0x4020..=0xFFFF => self.mapper.read(addr),
-      _ => 0,
-    }
+    self.last_read_addr = addr;
+    let (device, addr) = cpu_read_map(addr);
+    let value = match device {
+      CpuDevice::WorkRam => self.wram.read(addr),
+      CpuDevice::PpuRegisters => self.ppu_mem.read(&mut self.mapper, addr),
+      // the controller ports only drive the low bits
+      CpuDevice::Controllers => (self.input.read(addr) & 0b0001_1111) | (self.open_bus & 0b1110_0000),
+      // read inside the CPU, the data bus keeps its value and bit 5 isn't driven
+      CpuDevice::ApuStatus => return (self.apu_mem.read(addr) & 0b1101_1111) | (self.open_bus & 0b0010_0000),
+      CpuDevice::Cartridge => self.mapper.cpu_read(addr).unwrap_or(self.open_bus),
+      CpuDevice::ApuRegisters | CpuDevice::OamDma | CpuDevice::OpenBus => self.open_bus,
+    };
+    self.open_bus = value;
+    value
   }
 }
 
 impl MemWrite for Bus {
   fn write(&mut self, addr: usize, value: u8) {
-    let addr16 = addr as u16;
-    match addr16 {
-      0x0000..=0x0800 => self.wram.write(addr, value),
-      OAMDMA_CPU_ADDR => {self.oam_dma = (true, value, 0x00)},
-      0x2000..=0x2007 => self.ppu_mem.write(&mut self.mapper, addr, value),
-      0x4016 | 0x4017 => self.input.write(addr, value),
-      0x4000..=0x4017 => self.apu_mem.write(addr, value),
-      0x4020..=0xFFFF => self.mapper.write(addr, value),
-      _ => (),
+    self.open_bus = value;
+    let (device, addr) = cpu_write_map(addr);
+    match device {
+      CpuDevice::WorkRam => self.wram.write(addr, value),
+      CpuDevice::PpuRegisters => self.ppu_mem.write(&mut self.mapper, addr, value),
+      CpuDevice::OamDma => {self.oam_dma = (true, value, 0x00)},
+      CpuDevice::Controllers => self.input.write(addr, value),
+      CpuDevice::ApuRegisters | CpuDevice::ApuStatus => {
+        self.apu_mem.write(addr, value);
+        if let Some((delay, value)) = self.apu_mem.frame_counter.pending_write.take() {
+          self.scheduler.schedule_cpu_cycles(delay, Event::FrameCounterWrite(value));
+        }
+      },
+      CpuDevice::Cartridge => {
+        // FDS audio registers
+        if self.mapper.fds_audio() && (addr == 0x4023 || (0x4040..=0x409E).contains(&addr)) {
+          self.apu_mem.log_write(addr, value);
+        }
+        self.mapper.write(addr, value);
+        // mirroring is only switched by mapper register writes
+        self.ppu_mem.set_mirroring(self.mapper.mirroring());
+      },
+      CpuDevice::OpenBus => (),
     }
   }
 }
@@ -112,17 +174,125 @@ impl MemWrite for Bus {
 impl Bus {
   pub fn ppu_read(&mut self, addr: usize) -> u8 {
     match addr {
-      0x0000..=0x1FFF => self.mapper.read(addr),
-      0x2000..=0x3FFF => self.ppu_mem.ppu_read(&mut self.mapper, addr),
+      0x0000..=0x3FFF => self.ppu_mem.ppu_read(&mut self.mapper, addr),
+      _ => 0,
+    }
+  }
+
+  /// CPU read for the debugger, without side effects and leaving the open bus alone.
+  /// Registers, which can have read side effects, give the open bus value.
+  pub fn peek(&mut self, addr: usize) -> u8 {
+    let (device, addr) = cpu_read_map(addr);
+    match device {
+      CpuDevice::WorkRam => self.wram.read(addr),
+      CpuDevice::Cartridge => self.mapper.cpu_peek(addr).unwrap_or(self.open_bus),
+      _ => self.open_bus,
+    }
+  }
+
+  pub fn ppu_peek(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x0000..=0x3FFF => self.ppu_mem.ppu_peek(&mut self.mapper, addr),
       _ => 0,
     }
   }
 
   pub fn ppu_write(&mut self, addr: usize, value: u8) {
     match addr {
-      0x0000..=0x1FFF => self.mapper.write(addr, value),
-      0x2000..=0x3FFF => self.ppu_mem.ppu_write(&mut self.mapper, addr, value),
+      0x0000..=0x3FFF => self.ppu_mem.ppu_write(&mut self.mapper, addr, value),
       _ => (),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::nes::{cartridge::Cartridge, region::Region};
+
+  struct TestController {}
+
+  impl Controller for TestController {
+    fn read(&mut self, _addr: usize) -> u8 {
+      0x01
+    }
+  }
+
+  /// NROM with 8KB PRG-RAM, the PRG-ROM holds the low byte of the address
+  fn test_bus() -> Bus {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend((0..0x8000).map(|i| i as u8));
+    rom.extend(vec![0; 0x2000]);
+    let cart = Cartridge::create_from_rom(&rom).unwrap();
+    let mut bus = Bus::new(Box::new(TestController {}), Mixer::silent(48000), Scheduler::new(Region::Ntsc.clock_dividers()));
+    bus.load_mapper(mapper::load_rom(&cart).unwrap());
+    bus
+  }
+
+  #[test]
+  fn work_ram_mirrors() {
+    let mut bus = test_bus();
+    bus.write(0x0800, 0x42);
+    assert_eq!(bus.read(0x0000), 0x42);
+    assert_eq!(bus.read(0x1800), 0x42);
+    bus.write(0x07FF, 0x24);
+    assert_eq!(bus.read(0x1FFF), 0x24);
+  }
+
+  #[test]
+  fn ppu_register_mirrors() {
+    let mut bus = test_bus();
+    // $3456 is $2006, $3FFF is $2007
+    bus.write(0x3456, 0x21);
+    bus.write(0x2006, 0x08);
+    bus.write(0x3FFF, 0x77);
+    assert_eq!(bus.ppu_peek(0x2108), 0x77);
+  }
+
+  #[test]
+  fn apu_and_io_registers() {
+    let mut bus = test_bus();
+    bus.write(0x4014, 0x02);
+    assert!(bus.oam_dma.0);
+    assert_eq!(bus.read(0x4014), 0x02);
+    bus.write(0x0000, 0xFF);
+    bus.read(0x0000);
+    assert_eq!(bus.read(0x4016), 0xE1);
+    assert_eq!(bus.read(0x4017), 0xE1);
+  }
+
+  #[test]
+  fn open_bus() {
+    let mut bus = test_bus();
+    bus.write(0x0000, 0x5A);
+    assert_eq!(bus.read(0x0000), 0x5A);
+    for addr in [0x4000, 0x4013, 0x4018, 0x401F, 0x4020, 0x5FFF] {
+      assert_eq!(bus.read(addr), 0x5A);
+    }
+    // the last value driven is kept
+    assert_eq!(bus.read(0x8034), 0x34);
+    assert_eq!(bus.read(0x4018), 0x34);
+  }
+
+  #[test]
+  fn peek_leaves_open_bus() {
+    let mut bus = test_bus();
+    bus.write(0x0001, 0x99);
+    bus.write(0x0000, 0x5A);
+    assert_eq!(bus.peek(0x0001), 0x99);
+    assert_eq!(bus.peek(0x8034), 0x34);
+    assert_eq!(bus.peek(0x2002), 0x5A);
+    assert_eq!(bus.read(0x4018), 0x5A);
+  }
+
+  #[test]
+  fn cartridge_space() {
+    let mut bus = test_bus();
+    bus.write(0x6000, 0x11);
+    bus.write(0x7FFF, 0x22);
+    assert_eq!(bus.read(0x6000), 0x11);
+    assert_eq!(bus.read(0x7FFF), 0x22);
+    assert_eq!(bus.read(0x8000), 0x00);
+    assert_eq!(bus.read(0xFFFF), 0xFF);
+  }
+}
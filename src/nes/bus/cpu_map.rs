@@ -0,0 +1,137 @@
+use crate::nes::ppu::memory::OAMDMA_CPU_ADDR;
+
+/// Devices answering on the CPU bus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuDevice {
+  WorkRam,
+  PpuRegisters,
+  ApuRegisters,
+  /// $4015 reads, they don't drive the data bus
+  ApuStatus,
+  OamDma,
+  Controllers,
+  Cartridge,
+  /// Nothing answers, the data bus keeps its last value
+  OpenBus,
+}
+
+/// A range of the CPU address space, mirrored every `mirror` bytes
+struct CpuMapEntry {
+  start: usize,
+  end: usize,
+  mirror: usize,
+  read: CpuDevice,
+  write: CpuDevice,
+}
+
+const fn entry(start: usize, end: usize, mirror: usize, read: CpuDevice, write: CpuDevice) -> CpuMapEntry {
+  CpuMapEntry {start, end, mirror, read, write}
+}
+
+/// CPU memory map, from $0000 to $FFFF
+const CPU_MAP: [CpuMapEntry; 9] = [
+  // 2KB of work RAM, mirrored 4 times
+  entry(0x0000, 0x1FFF, 0x0800, CpuDevice::WorkRam, CpuDevice::WorkRam),
+  // the 8 PPU registers, mirrored up to $3FFF
+  entry(0x2000, 0x3FFF, 0x0008, CpuDevice::PpuRegisters, CpuDevice::PpuRegisters),
+  entry(0x4000, 0x4013, 0x0014, CpuDevice::OpenBus, CpuDevice::ApuRegisters),
+  entry(OAMDMA_CPU_ADDR as usize, OAMDMA_CPU_ADDR as usize, 0x0001, CpuDevice::OpenBus, CpuDevice::OamDma),
+  entry(0x4015, 0x4015, 0x0001, CpuDevice::ApuStatus, CpuDevice::ApuRegisters),
+  entry(0x4016, 0x4016, 0x0001, CpuDevice::Controllers, CpuDevice::Controllers),
+  // controller 2 on reads, frame counter on writes
+  entry(0x4017, 0x4017, 0x0001, CpuDevice::Controllers, CpuDevice::ApuRegisters),
+  // APU test mode registers, disabled on retail consoles
+  entry(0x4018, 0x401F, 0x0008, CpuDevice::OpenBus, CpuDevice::OpenBus),
+  entry(0x4020, 0xFFFF, 0xBFE0, CpuDevice::Cartridge, CpuDevice::Cartridge),
+];
+
+fn lookup(addr: usize) -> Option<&'static CpuMapEntry> {
+  CPU_MAP.iter().find(|e| (e.start..=e.end).contains(&addr))
+}
+
+/// Device read at `addr` and the address once the mirroring is removed
+pub fn cpu_read_map(addr: usize) -> (CpuDevice, usize) {
+  match lookup(addr) {
+    Some(e) => (e.read, e.start + (addr - e.start) % e.mirror),
+    None => (CpuDevice::OpenBus, addr),
+  }
+}
+
+/// Device written at `addr` and the address once the mirroring is removed
+pub fn cpu_write_map(addr: usize) -> (CpuDevice, usize) {
+  match lookup(addr) {
+    Some(e) => (e.write, e.start + (addr - e.start) % e.mirror),
+    None => (CpuDevice::OpenBus, addr),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_bounds() {
+    for e in CPU_MAP.iter() {
+      assert_eq!(cpu_read_map(e.start), (e.read, e.start));
+      assert_eq!(cpu_write_map(e.start), (e.write, e.start));
+      let last = e.start + (e.end - e.start) % e.mirror;
+      assert_eq!(cpu_read_map(e.end), (e.read, last));
+      assert_eq!(cpu_write_map(e.end), (e.write, last));
+    }
+  }
+
+  #[test]
+  fn entries_cover_address_space() {
+    assert_eq!(CPU_MAP[0].start, 0x0000);
+    assert_eq!(CPU_MAP[CPU_MAP.len() - 1].end, 0xFFFF);
+    for pair in CPU_MAP.windows(2) {
+      assert_eq!(pair[0].end + 1, pair[1].start);
+    }
+  }
+
+  #[test]
+  fn work_ram_mirrors() {
+    for (addr, mirrored) in [(0x0000, 0x0000), (0x07FF, 0x07FF), (0x0800, 0x0000), (0x1000, 0x0000), (0x1FFF, 0x07FF)] {
+      assert_eq!(cpu_read_map(addr), (CpuDevice::WorkRam, mirrored));
+      assert_eq!(cpu_write_map(addr), (CpuDevice::WorkRam, mirrored));
+    }
+  }
+
+  #[test]
+  fn ppu_register_mirrors() {
+    for (addr, mirrored) in [(0x2000, 0x2000), (0x2007, 0x2007), (0x2008, 0x2000), (0x3456, 0x2006), (0x3FFF, 0x2007)] {
+      assert_eq!(cpu_read_map(addr), (CpuDevice::PpuRegisters, mirrored));
+      assert_eq!(cpu_write_map(addr), (CpuDevice::PpuRegisters, mirrored));
+    }
+  }
+
+  #[test]
+  fn apu_and_io_registers() {
+    assert_eq!(cpu_read_map(0x4000), (CpuDevice::OpenBus, 0x4000));
+    assert_eq!(cpu_write_map(0x4013), (CpuDevice::ApuRegisters, 0x4013));
+    assert_eq!(cpu_read_map(0x4014), (CpuDevice::OpenBus, 0x4014));
+    assert_eq!(cpu_write_map(0x4014), (CpuDevice::OamDma, 0x4014));
+    assert_eq!(cpu_read_map(0x4015), (CpuDevice::ApuStatus, 0x4015));
+    assert_eq!(cpu_write_map(0x4015), (CpuDevice::ApuRegisters, 0x4015));
+    assert_eq!(cpu_read_map(0x4016), (CpuDevice::Controllers, 0x4016));
+    assert_eq!(cpu_write_map(0x4016), (CpuDevice::Controllers, 0x4016));
+    assert_eq!(cpu_read_map(0x4017), (CpuDevice::Controllers, 0x4017));
+    assert_eq!(cpu_write_map(0x4017), (CpuDevice::ApuRegisters, 0x4017));
+  }
+
+  #[test]
+  fn test_mode_registers_are_open_bus() {
+    for addr in 0x4018..=0x401F {
+      assert_eq!(cpu_read_map(addr), (CpuDevice::OpenBus, addr));
+      assert_eq!(cpu_write_map(addr), (CpuDevice::OpenBus, addr));
+    }
+  }
+
+  #[test]
+  fn cartridge_space() {
+    for addr in [0x4020, 0x6000, 0x8000, 0xFFFF] {
+      assert_eq!(cpu_read_map(addr), (CpuDevice::Cartridge, addr));
+      assert_eq!(cpu_write_map(addr), (CpuDevice::Cartridge, addr));
+    }
+  }
+}
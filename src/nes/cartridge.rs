@@ -1,9 +1,18 @@
-//use std::error::Error;
+pub mod database;
+pub mod disk;
+pub mod hash;
+pub mod nsf;
+
+use std::error::Error;
 use std::fmt;
+use std::io;
 
 use crate::nes::{
   mapper::MirroringType,
 };
+use database::GameEntry;
+use disk::DiskImage;
+use nsf::NsfInfo;
 
 #[allow(non_snake_case)]
 #[allow(non_camel_case_types)]
@@ -16,7 +25,7 @@ pub enum ConsoleType {
 }
 
 #[allow(non_camel_case_types)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TimingType {
   NTSC_NES,
   PAL_NES,
@@ -76,26 +85,17 @@ impl fmt::Display for NesHeader {
   }
 }
 
+const INES_PRG_RAM_DEFAULT: usize = 8 * 1024;
+const INES_CHR_RAM_DEFAULT: usize = 8 * 1024;
+
 impl NesHeader {
   pub fn new(header: &[u8]) -> Self {
+    let nes2 = header[7] & 0b0000_1100 == 8;
+    let prg_rom_size = Self::rom_size(header[4], if nes2 {header[9] & 0b0000_1111} else {0}, 4 + 10);
+    let chr_rom_size = Self::rom_size(header[5], if nes2 {(header[9] & 0b1111_0000) >> 4} else {0}, 3 + 10);
     Self {
-      prg_rom_size: {
-        if (header[9] & 0b00001111) == 0xF {
-          (2 << (((header[4] & 0b1111_1100) as usize) >> 2)) * ((header[4] & 0b0000_0011) * 2 + 1) as usize
-        }
-        else {
-          ((((header[9] & 0b00001111) as usize) << 8) | (header[4] as usize)) << (4 + 10)
-        }
-      },
-      chr_rom_size: {
-        if ((header[9] & 0b11110000) >> 4) == 0xF {
-          (2 << (((header[5] & 0b1111_1100) as usize) >> 2)) * ((header[5] & 0b0000_0011) * 2 + 1) as usize
-        }
-        else {
-          ((((header[9] & 0b11110000) as usize) << 4) | (header[5] as usize)) << (3 + 10)
-        }
-      },
-          
+      prg_rom_size,
+      chr_rom_size,
 
       //prg_rom_size: (header[4] as usize) * 16 * 1024,
       //chr_rom_size: (header[5] as usize) * 8 * 1024,
@@ -112,9 +112,14 @@ impl NesHeader {
       battery: if header[6] & 0b0000_0010 != 0 {true} else {false},
       trainer: if header[6] & 0b0000_0100 != 0 {true} else {false},
       mapper_num: {
-        ((header[6] >> 4) as u16)
-          | ((header[7] & 0b1111_0000) as u16)
-          | (((header[8] & 0b0000_1111) as u16) << 8)
+        if nes2 {
+          ((header[6] >> 4) as u16)
+            | ((header[7] & 0b1111_0000) as u16)
+            | (((header[8] & 0b0000_1111) as u16) << 8)
+        }
+        else {
+          ((header[6] >> 4) as u16) | ((header[7] & 0b1111_0000) as u16)
+        }
       },
       console_type: {
         match header[7] & 0b0000_0011 {
@@ -125,14 +130,34 @@ impl NesHeader {
           _ => ConsoleType::NES,
         }
       },
-      nes2: if header[7] & 0b0000_1100 == 8 {true} else {false},
-      submapper_num: (header[8] & 0b1111_0000) >> 4,
-      eeprom_size: if header[10] & 0b0000_1111 == 0 {0} else {64 << ((header[10] & 0b0000_1111) as usize)},
-      prg_ram_size: if header[10] & 0b1111_0000 == 0 {0} else {64 << (((header[10] & 0b1111_0000) >> 4) as usize)},
-      chr_ram_size: if header[11] & 0b0000_1111 == 0 {0} else {64 << ((header[11] & 0b0000_1111) as usize)},
-      chr_nvram_size: if header[11] & 0b1111_0000 == 0 {0} else {64 << (((header[11] & 0b1111_0000) >> 4) as usize)},
+      nes2,
+      submapper_num: if nes2 {(header[8] & 0b1111_0000) >> 4} else {0},
+      eeprom_size: if nes2 {Self::ram_size(header[10] >> 4)} else {0},
+      prg_ram_size: {
+        if nes2 {
+          Self::ram_size(header[10] & 0b0000_1111)
+        }
+        else if header[8] == 0 {
+          INES_PRG_RAM_DEFAULT
+        }
+        else {
+          (header[8] as usize) * INES_PRG_RAM_DEFAULT
+        }
+      },
+      chr_ram_size: {
+        if nes2 {
+          Self::ram_size(header[11] & 0b0000_1111)
+        }
+        else if chr_rom_size == 0 {
+          INES_CHR_RAM_DEFAULT
+        }
+        else {
+          0
+        }
+      },
+      chr_nvram_size: if nes2 {Self::ram_size(header[11] >> 4)} else {0},
       timing_type: {
-        match header[12] & 0b0000_0011 {
+        match if nes2 {header[12] & 0b0000_0011} else {header[9] & 0b0000_0001} {
           0 => TimingType::NTSC_NES,
           1 => TimingType::PAL_NES,
           2 => TimingType::MUL_REG,
@@ -140,43 +165,275 @@ impl NesHeader {
           _ => TimingType::NTSC_NES,
         }
       },
-      misc_roms: header[14] & 3,
-      default_exp_device: header[15] & 0b0011_1111,
+      misc_roms: if nes2 {header[14] & 3} else {0},
+      default_exp_device: if nes2 {header[15] & 0b0011_1111} else {0},
+    }
+  }
+
+  /// PRG/CHR-ROM size, `unit_shift` is 14 for PRG (16KB unit) and 13 for CHR (8KB unit).
+  /// A MSB nibble of 0xF selects the NES 2.0 exponent-multiplier notation:
+  /// EEEE_EEMM => 2^E * (MM * 2 + 1) bytes.
+  fn rom_size(lsb: u8, msb: u8, unit_shift: usize) -> usize {
+    if msb == 0xF {
+      let exponent = (lsb >> 2) as u32;
+      let multiplier = ((lsb & 0b0000_0011) as usize) * 2 + 1;
+      2usize.checked_pow(exponent).unwrap_or(0).saturating_mul(multiplier)
+    }
+    else {
+      (((msb as usize) << 8) | (lsb as usize)) << unit_shift
+    }
+  }
+
+  /// NES 2.0 RAM size, a shift count of 0 means no RAM, otherwise 64 << shift_count bytes.
+  fn ram_size(shift_count: u8) -> usize {
+    let shift_count = shift_count & 0b0000_1111;
+    if shift_count == 0 {0} else {64 << (shift_count as usize)}
+  }
+
+  /// Volatile + battery backed PRG-RAM
+  pub fn prg_ram_total(&self) -> usize {
+    self.prg_ram_size + self.eeprom_size
+  }
+
+  /// Volatile + battery backed CHR-RAM
+  pub fn chr_ram_total(&self) -> usize {
+    self.chr_ram_size + self.chr_nvram_size
+  }
+}
+
+pub const HEADER_SIZE: usize = 16;
+pub const INES_MAGIC: &[u8] = b"NES\x1A";
+const TRAINER_SIZE: usize = 512;
+pub const TRAINER_ADDR: usize = 0x7000;
+pub const FDS_BIOS_SIZE: usize = 8 * 1024;
+
+/// NES 2.0 header of the RAM adapter: mapper 20, the 8KB BIOS as PRG-ROM
+/// (exponent-multiplier size 2^13), 32KB PRG-RAM and 8KB CHR-RAM.
+const FDS_HEADER: [u8; HEADER_SIZE] = [
+  b'N', b'E', b'S', 0x1A, 13 << 2, 0, 0x40, 0x18, 0, 0x0F, 9, 7, 0, 0, 0, 0,
+];
+
+/// NES 2.0 header of the NSF player: mapper 31 (same bank switching), 8KB CHR-RAM.
+const NSF_HEADER: [u8; HEADER_SIZE] = [
+  b'N', b'E', b'S', 0x1A, 0, 0, 0xF0, 0x18, 0, 0, 7, 7, 0, 0, 0, 0,
+];
+
+#[derive(Debug)]
+pub enum RomError {
+  Io(io::Error),
+  TooShort(usize),
+  BadMagic,
+  TruncatedTrainer,
+  TruncatedPrg{expected: usize, found: usize},
+  TruncatedChr{expected: usize, found: usize},
+  UnsupportedFormat(String),
+  Malformed(String),
+  BadNes2Size(String),
+  MissingBios(String),
+  Patch{filename: String, msg: String},
+}
+
+impl fmt::Display for RomError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      RomError::Io(e) => write!(f, "can't read rom: {}", e),
+      RomError::TooShort(len) => write!(f, "file too short to be a rom ({} bytes)", len),
+      RomError::BadMagic => write!(f, "not an iNES/NES 2.0 rom (bad magic number)"),
+      RomError::TruncatedTrainer => write!(f, "truncated rom: header announces a trainer that is missing"),
+      RomError::TruncatedPrg{expected, found} => {
+        write!(f, "truncated PRG-ROM: expected {:#x} bytes, found {:#x}", expected, found)
+      },
+      RomError::TruncatedChr{expected, found} => {
+        write!(f, "truncated CHR-ROM: expected {:#x} bytes, found {:#x}", expected, found)
+      },
+      RomError::UnsupportedFormat(msg) => write!(f, "unsupported rom format: {}", msg),
+      RomError::Malformed(msg) => write!(f, "malformed rom: {}", msg),
+      RomError::BadNes2Size(msg) => write!(f, "invalid NES 2.0 size: {}", msg),
+      RomError::MissingBios(msg) => write!(f, "FDS BIOS: {}", msg),
+      RomError::Patch{filename, msg} => write!(f, "can't apply patch \"{}\": {}", filename, msg),
     }
   }
 }
 
+impl Error for RomError {}
+
+impl From<io::Error> for RomError {
+  fn from(e: io::Error) -> Self {
+    RomError::Io(e)
+  }
+}
+
 pub struct Cartridge {
   pub header: NesHeader,
+  pub game: Option<GameEntry>,
+  pub trainer: Option<Vec<u8>>,
   pub prg_rom : Vec<u8>,
   pub prg_size : usize,
   pub chr_rom : Option<Vec<u8>>,
   pub chr_size : usize,
+  pub disk: Option<DiskImage>,
+  pub nsf: Option<NsfInfo>,
 }
 
 impl Cartridge {
-  pub fn create_from_rom(rom: &Vec<u8>) -> Self {
-    let header = NesHeader::new(&rom[..16]);
+  pub fn check_header(rom: &[u8]) -> Result<(), RomError> {
+    if rom.len() < HEADER_SIZE {
+      return Err(RomError::TooShort(rom.len()));
+    }
+    if &rom[..INES_MAGIC.len()] != INES_MAGIC {
+      return Err(RomError::BadMagic);
+    }
+    Ok(())
+  }
+
+  fn check_sizes(rom: &[u8], header: &NesHeader) -> Result<(), RomError> {
+    if header.prg_rom_size == 0 {
+      return Err(if header.nes2 {
+        RomError::BadNes2Size("PRG-ROM size is 0 or overflows".to_string())
+      } else {
+        RomError::UnsupportedFormat("no PRG-ROM".to_string())
+      });
+    }
+    if header.nes2 && header.prg_rom_size.saturating_add(header.chr_rom_size) > usize::MAX / 2 {
+      return Err(RomError::BadNes2Size("PRG/CHR-ROM size overflows".to_string()));
+    }
+    let start = Self::prg_rom_start(header);
+    if rom.len() < start {
+      return Err(RomError::TruncatedTrainer);
+    }
+    let available = rom.len() - start;
+    if available < header.prg_rom_size {
+      return Err(RomError::TruncatedPrg{expected: header.prg_rom_size, found: available});
+    }
+    let available = available - header.prg_rom_size;
+    if available < header.chr_rom_size {
+      return Err(RomError::TruncatedChr{expected: header.chr_rom_size, found: available});
+    }
+    Ok(())
+  }
+
+  pub fn create_from_rom(rom: &[u8]) -> Result<Self, RomError> {
+    Self::check_header(rom)?;
+    let mut header = NesHeader::new(&rom[..HEADER_SIZE]);
+    Self::check_sizes(rom, &header)?;
+    let game = Self::database_lookup(rom, &header);
+    if let Some(game) = &game {
+      println!("ROM database: {}", game);
+      game.apply(&mut header);
+      Self::check_sizes(rom, &header)?;
+    }
+    // the trainer is copied to $7000-$71FF, the PRG-RAM must cover it
+    if header.trainer && header.prg_ram_total() < INES_PRG_RAM_DEFAULT {
+      println!("PRG-RAM size {:#x} too small for the trainer, using {:#x}", header.prg_ram_total(), INES_PRG_RAM_DEFAULT);
+      header.prg_ram_size = INES_PRG_RAM_DEFAULT - header.eeprom_size;
+    }
     let cart = Cartridge {
+      game,
+      trainer: if header.trainer {Some(rom[HEADER_SIZE..(HEADER_SIZE + TRAINER_SIZE)].to_vec())} else {None},
       //prg_rom: rom[rom_info.start_prg..(rom_info.start_prg + rom_info.size_prg)].to_vec(),
       prg_rom: Self::prg_rom_vec(rom, &header),
       prg_size: header.prg_rom_size,
       chr_rom: if header.chr_rom_size == 0 {None} else {Some(Self::chr_rom_vec(rom, &header))},
-      chr_size: header.prg_rom_size,
+      chr_size: header.chr_rom_size,
+      disk: None,
+      nsf: None,
       header,
     };
     println!("");
     println!("{}", cart.header);
-    cart
+    if cart.trainer.is_some() {
+      println!("Trainer loaded at {:#06x}", TRAINER_ADDR);
+    }
+    Ok(cart)
+  }
+
+  /// Famicom Disk System: the BIOS takes the place of the PRG-ROM.
+  pub fn create_from_disk(image: &[u8], bios: &[u8]) -> Result<Self, RomError> {
+    if bios.len() != FDS_BIOS_SIZE {
+      return Err(RomError::MissingBios(format!("expected {:#x} bytes, found {:#x}", FDS_BIOS_SIZE, bios.len())));
+    }
+    let disk = DiskImage::from_bytes(image)?;
+    let header = NesHeader::new(&FDS_HEADER);
+    println!("FDS disk image: {:?}, {} sides", disk.format, disk.sides.len());
+    Ok(Cartridge {
+      game: None,
+      trainer: None,
+      prg_rom: bios.to_vec(),
+      prg_size: header.prg_rom_size,
+      chr_rom: None,
+      chr_size: 0,
+      disk: Some(disk),
+      nsf: None,
+      header,
+    })
+  }
+
+  /// NSF/NSFe music file, played by a built-in driver instead of a game.
+  pub fn create_from_nsf(file: &[u8]) -> Result<Self, RomError> {
+    let nsf = NsfInfo::from_bytes(file)?;
+    let mut header = NesHeader::new(&NSF_HEADER);
+    header.timing_type = nsf.timing_type;
+    println!("NSF: {} - {} ({}), {} songs", nsf.title, nsf.artist, nsf.copyright, nsf.total_songs);
+    Ok(Cartridge {
+      game: None,
+      trainer: None,
+      prg_rom: nsf.data.clone(),
+      prg_size: nsf.data.len(),
+      chr_rom: None,
+      chr_size: 0,
+      disk: None,
+      nsf: Some(nsf),
+      header,
+    })
   }
 
-  fn prg_rom_vec(rom: &Vec<u8>, header: &NesHeader) -> Vec<u8> {
-    let start: usize = 16;
+  /// Hashes PRG+CHR as sized by the header, then everything after the
+  /// header in case the sizes themselves are wrong.
+  fn database_lookup(rom: &[u8], header: &NesHeader) -> Option<GameEntry> {
+    let start = Self::prg_rom_start(header);
+    let end = start + header.prg_rom_size + header.chr_rom_size;
+    if end <= rom.len() {
+      if let Some(game) = database::lookup(&rom[start..end]) {
+        return Some(game);
+      }
+    }
+    if end != rom.len() && start < rom.len() {
+      return database::lookup(&rom[start..]);
+    }
+    None
+  }
+
+  fn prg_rom_start(header: &NesHeader) -> usize {
+    if header.trainer {HEADER_SIZE + TRAINER_SIZE} else {HEADER_SIZE}
+  }
+
+  fn prg_rom_vec(rom: &[u8], header: &NesHeader) -> Vec<u8> {
+    let start: usize = Self::prg_rom_start(header);
     rom[start..(start + (header.prg_rom_size as usize))].to_vec()
   }
 
-  fn chr_rom_vec(rom: &Vec<u8>, header: &NesHeader) -> Vec<u8> {
-    let start: usize = 16 + (header.prg_rom_size as usize);
+  fn chr_rom_vec(rom: &[u8], header: &NesHeader) -> Vec<u8> {
+    let start: usize = Self::prg_rom_start(header) + (header.prg_rom_size as usize);
     rom[start..(start + (header.chr_rom_size as usize))].to_vec()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::nes::{mapper, memory::MemRead};
+
+  #[test]
+  fn trainer_without_prg_ram() {
+    // NES 2.0, NROM with a trainer and no PRG-RAM, 16KB PRG-ROM and 8KB CHR-ROM
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0x04, 0x08, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend((0..TRAINER_SIZE).map(|i| i as u8));
+    rom.extend(vec![0xEA; 0x4000 + 0x2000]);
+    let cart = Cartridge::create_from_rom(&rom).unwrap();
+    assert_eq!(cart.header.prg_ram_total(), INES_PRG_RAM_DEFAULT);
+    let mut mapper = mapper::load_rom(&cart).unwrap();
+    assert_eq!(mapper.read(TRAINER_ADDR), 0x00);
+    assert_eq!(mapper.read(TRAINER_ADDR + 0x1FF), 0xFF);
+    assert_eq!(mapper.read(0x8000), 0xEA);
+  }
+}
@@ -0,0 +1,232 @@
+use std::{fmt, fs, io};
+use std::sync::OnceLock;
+
+use crate::nes::{
+  mapper::MirroringType,
+  cartridge::{NesHeader, TimingType, hash},
+};
+
+/// nes20db.xml plus the NES 2.0 XML export given at build time, see build.rs
+const DATABASE: &str = include_str!(concat!(env!("OUT_DIR"), "/nes20db.xml"));
+/// Full NES 2.0 XML database loaded at startup, searched before the built-in entries
+static EXTERNAL_DATABASE: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct GameEntry {
+  pub title: String,
+  pub crc32: Option<u32>,
+  pub sha1: Option<[u8; 20]>,
+
+  pub prg_rom_size: usize,
+  pub chr_rom_size: usize,
+  pub prg_ram_size: usize,
+  pub prg_nvram_size: usize,
+  pub chr_ram_size: usize,
+  pub chr_nvram_size: usize,
+
+  pub mapper_num: u16,
+  pub submapper_num: u8,
+  pub mirroring_type: Option<MirroringType>,
+  pub battery: bool,
+  pub timing_type: TimingType,
+}
+
+impl fmt::Display for GameEntry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} ({:?}, mapper {}.{})", self.title, self.timing_type, self.mapper_num, self.submapper_num)
+  }
+}
+
+/// Loads a NES 2.0 XML database such as nes20db.xml, returns its number of games.
+/// Only the first database loaded is kept.
+pub fn load_external(filename: &str) -> io::Result<usize> {
+  let xml = fs::read_to_string(filename)?;
+  let games = xml.matches("<game>").count();
+  if games == 0 {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "no <game> entry, not a NES 2.0 XML database"));
+  }
+  let _ = EXTERNAL_DATABASE.set(xml);
+  Ok(games)
+}
+
+/// Looks up PRG-ROM + CHR-ROM (no header, no trainer) by CRC32 or SHA-1.
+pub fn lookup(data: &[u8]) -> Option<GameEntry> {
+  let crc32 = hash::crc32(data);
+  let sha1 = hash::sha1(data);
+  entries().find(|entry| entry.crc32 == Some(crc32) || entry.sha1 == Some(sha1))
+}
+
+fn entries() -> impl Iterator<Item = GameEntry> {
+  EXTERNAL_DATABASE.get().map(String::as_str).into_iter()
+    .chain(std::iter::once(DATABASE))
+    .flat_map(|xml| xml.split("<game>").skip(1))
+    .filter_map(|game| game.split("</game>").next())
+    .filter_map(parse_game)
+}
+
+fn parse_game(game: &str) -> Option<GameEntry> {
+  let rom = element(game, "rom")?;
+  let pcb = element(game, "pcb")?;
+  Some(GameEntry {
+    title: game.split("<!--").nth(1)
+      .and_then(|c| c.split("-->").next())
+      .map(|c| c.trim().to_string())
+      .unwrap_or_default(),
+    crc32: attr(rom, "crc32").and_then(|v| u32::from_str_radix(v, 16).ok()),
+    sha1: attr(rom, "sha1").and_then(parse_sha1),
+
+    prg_rom_size: size_of(game, "prgrom"),
+    chr_rom_size: size_of(game, "chrrom"),
+    prg_ram_size: size_of(game, "prgram"),
+    prg_nvram_size: size_of(game, "prgnvram"),
+    chr_ram_size: size_of(game, "chrram"),
+    chr_nvram_size: size_of(game, "chrnvram"),
+
+    mapper_num: attr(pcb, "mapper").and_then(|v| v.parse().ok()).unwrap_or(0),
+    submapper_num: attr(pcb, "submapper").and_then(|v| v.parse().ok()).unwrap_or(0),
+    mirroring_type: match attr(pcb, "mirroring") {
+      Some("H") => Some(MirroringType::Horizontal),
+      Some("V") => Some(MirroringType::Vertical),
+      Some("4") => Some(MirroringType::FourScreen),
+      _ => None,
+    },
+    battery: attr(pcb, "battery") == Some("1"),
+    timing_type: match element(game, "console").and_then(|c| attr(c, "region")) {
+      Some("1") => TimingType::PAL_NES,
+      Some("2") => TimingType::MUL_REG,
+      Some("3") => TimingType::Dendy,
+      _ => TimingType::NTSC_NES,
+    },
+  })
+}
+
+/// Attributes of the first `<name .../>` element in `game`.
+fn element<'a>(game: &'a str, name: &str) -> Option<&'a str> {
+  let start = game.find(&format!("<{} ", name))? + name.len() + 2;
+  let end = game[start..].find('>')? + start;
+  Some(&game[start..end])
+}
+
+fn attr<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+  let pattern = format!("{}=\"", name);
+  let mut search = element;
+  loop {
+    let pos = search.find(&pattern)?;
+    let value_start = pos + pattern.len();
+    // avoid matching "prgnvram" when looking for "ram" etc.
+    if pos == 0 || search.as_bytes()[pos - 1].is_ascii_whitespace() {
+      let value_len = search[value_start..].find('"')?;
+      return Some(&search[value_start..(value_start + value_len)]);
+    }
+    search = &search[value_start..];
+  }
+}
+
+fn size_of(game: &str, name: &str) -> usize {
+  element(game, name)
+    .and_then(|e| attr(e, "size"))
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(0)
+}
+
+fn parse_sha1(value: &str) -> Option<[u8; 20]> {
+  if value.len() != 40 {
+    return None;
+  }
+  let mut digest = [0u8; 20];
+  for (i, byte) in digest.iter_mut().enumerate() {
+    *byte = u8::from_str_radix(&value[i * 2..i * 2 + 2], 16).ok()?;
+  }
+  Some(digest)
+}
+
+impl GameEntry {
+  /// Replaces the header fields with the database ones, warning for each disagreement.
+  /// The ROM sizes are corrected too, so the ROM must be sliced afterwards.
+  pub fn apply(&self, header: &mut NesHeader) {
+    if header.mapper_num != self.mapper_num || header.submapper_num != self.submapper_num {
+      println!("ROM database: header mapper {}.{} corrected to {}.{}"
+        , header.mapper_num, header.submapper_num, self.mapper_num, self.submapper_num);
+      header.mapper_num = self.mapper_num;
+      header.submapper_num = self.submapper_num;
+    }
+    if let Some(mirroring_type) = self.mirroring_type {
+      if header.mirroring_type != mirroring_type {
+        println!("ROM database: header mirroring {} corrected to {}", header.mirroring_type, mirroring_type);
+        header.mirroring_type = mirroring_type;
+      }
+    }
+    if header.battery != self.battery {
+      println!("ROM database: header battery {} corrected to {}", header.battery, self.battery);
+      header.battery = self.battery;
+    }
+    if self.prg_rom_size != 0
+        && (header.prg_rom_size != self.prg_rom_size || header.chr_rom_size != self.chr_rom_size) {
+      println!("ROM database: header PRG/CHR-ROM size {:#x}/{:#x} corrected to {:#x}/{:#x}"
+        , header.prg_rom_size, header.chr_rom_size, self.prg_rom_size, self.chr_rom_size);
+      header.prg_rom_size = self.prg_rom_size;
+      header.chr_rom_size = self.chr_rom_size;
+    }
+    if header.nes2 && (header.prg_ram_size != self.prg_ram_size
+        || header.eeprom_size != self.prg_nvram_size
+        || header.chr_ram_size != self.chr_ram_size
+        || header.chr_nvram_size != self.chr_nvram_size) {
+      println!("ROM database: header RAM sizes corrected");
+    }
+    header.prg_ram_size = self.prg_ram_size;
+    header.eeprom_size = self.prg_nvram_size;
+    header.chr_ram_size = self.chr_ram_size;
+    header.chr_nvram_size = self.chr_nvram_size;
+    if header.timing_type != self.timing_type {
+      println!("ROM database: header timing {:?} corrected to {:?}", header.timing_type, self.timing_type);
+      header.timing_type = self.timing_type;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::nes::cartridge::{Cartridge, HEADER_SIZE};
+
+  #[test]
+  fn bundled_entry() {
+    let smb = entries().find(|entry| entry.crc32 == Some(0x3337EC46)).unwrap();
+    assert_eq!((smb.prg_rom_size, smb.chr_rom_size), (0x8000, 0x2000));
+    assert_eq!(smb.mirroring_type, Some(MirroringType::Vertical));
+  }
+
+  #[test]
+  fn bad_header_corrected() {
+    // 32KB PRG + 8KB CHR, the database says MMC1, horizontal, battery backed
+    let data: Vec<u8> = (0..0xA000u32).map(|i| (i.wrapping_mul(31) >> 3) as u8).collect();
+    let xml = format!(concat!(
+      "<nes20db><game>\n<!-- Test Game (USA) -->\n",
+      "<prgrom size=\"32768\"/>\n<chrrom size=\"8192\"/>\n<prgnvram size=\"8192\"/>\n",
+      "<rom size=\"40960\" crc32=\"{:08X}\"/>\n<console type=\"0\" region=\"1\"/>\n",
+      "<pcb mapper=\"1\" submapper=\"0\" mirroring=\"H\" battery=\"1\"/>\n</game></nes20db>\n"),
+      hash::crc32(&data));
+    let filename = std::env::temp_dir().join(format!("nes20db-test-{}.xml", std::process::id()));
+    fs::write(&filename, xml).unwrap();
+    assert_eq!(load_external(filename.to_str().unwrap()).unwrap(), 1);
+    fs::remove_file(&filename).unwrap();
+
+    let game = lookup(&data).unwrap();
+    assert_eq!(game.title, "Test Game (USA)");
+    assert_eq!(game.mapper_num, 1);
+    assert!(lookup(&data[1..]).is_none());
+
+    // iNES header: mapper 0, vertical, no battery, and a 16KB PRG-ROM size
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(&data);
+    let cart = Cartridge::create_from_rom(&rom).unwrap();
+    assert_eq!(cart.header.mapper_num, 1);
+    assert_eq!(cart.header.mirroring_type, MirroringType::Horizontal);
+    assert!(cart.header.battery);
+    assert_eq!(cart.header.timing_type, TimingType::PAL_NES);
+    assert_eq!(cart.header.eeprom_size, 0x2000);
+    assert_eq!(cart.prg_rom, data[..0x8000]);
+    assert_eq!(cart.chr_rom.as_deref(), Some(&data[0x8000..]));
+    assert_eq!(rom.len(), HEADER_SIZE + cart.prg_size + cart.chr_size);
+  }
+}
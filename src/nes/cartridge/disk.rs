@@ -0,0 +1,165 @@
+use crate::nes::cartridge::RomError;
+
+pub const FDS_MAGIC: &[u8] = b"FDS\x1A";
+const FDS_HEADER_SIZE: usize = 16;
+pub const SIDE_SIZE: usize = 65500;
+const QD_SIDE_SIZE: usize = 65536;
+const DISK_INFO_MAGIC: &[u8] = b"\x01*NINTENDO-HVC*";
+
+const BLOCK_DISK_INFO: u8 = 1;
+const BLOCK_FILE_AMOUNT: u8 = 2;
+const BLOCK_FILE_HEADER: u8 = 3;
+const BLOCK_FILE_DATA: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DiskFormat {
+  Fds,
+  FdsHeaderless,
+  /// QuickDisk dump, 64KB sides with the 2 CRC bytes after each block
+  Qd,
+}
+
+/// Famicom Disk System image, each side is kept in the .fds layout
+/// (blocks without gaps and CRCs), `to_bytes` gives back the original layout.
+#[derive(Debug, Clone)]
+pub struct DiskImage {
+  pub format: DiskFormat,
+  pub sides: Vec<Vec<u8>>,
+}
+
+pub fn is_disk_image(file: &[u8]) -> bool {
+  file.starts_with(FDS_MAGIC) || file.starts_with(DISK_INFO_MAGIC)
+}
+
+/// Length of the block starting at `block`, `file_size` comes from the previous file header.
+pub fn block_len(block: &[u8], file_size: usize) -> Option<usize> {
+  match block.first() {
+    Some(&BLOCK_DISK_INFO) => Some(56),
+    Some(&BLOCK_FILE_AMOUNT) => Some(2),
+    Some(&BLOCK_FILE_HEADER) => Some(16),
+    Some(&BLOCK_FILE_DATA) => Some(1 + file_size),
+    _ => None,
+  }
+}
+
+/// File size announced by a file header block.
+pub fn file_header_size(block: &[u8]) -> usize {
+  if block.len() >= 15 && block[0] == BLOCK_FILE_HEADER {
+    (block[13] as usize) | ((block[14] as usize) << 8)
+  }
+  else {
+    0
+  }
+}
+
+/// FDS block CRC, computed over the 0x80 start mark and the block.
+pub fn crc(data: &[u8]) -> u16 {
+  let mut crc: u16 = 0;
+  for value in data.iter().chain([0u8, 0u8].iter()) {
+    let mut n: u8 = 0x01;
+    loop {
+      let carry = crc & 1;
+      crc >>= 1;
+      if carry != 0 {
+        crc ^= 0x8408;
+      }
+      if value & n != 0 {
+        crc ^= 0x8000;
+      }
+      if n == 0x80 {
+        break;
+      }
+      n <<= 1;
+    }
+  }
+  crc
+}
+
+impl DiskImage {
+  pub fn from_bytes(file: &[u8]) -> Result<Self, RomError> {
+    let (format, data) = if file.starts_with(FDS_MAGIC) {
+      (DiskFormat::Fds, &file[FDS_HEADER_SIZE.min(file.len())..])
+    }
+    else if file.len() % QD_SIDE_SIZE == 0 && file.len() % SIDE_SIZE != 0 {
+      (DiskFormat::Qd, file)
+    }
+    else {
+      (DiskFormat::FdsHeaderless, file)
+    };
+    let side_size = if format == DiskFormat::Qd {QD_SIDE_SIZE} else {SIDE_SIZE};
+    if data.len() < side_size {
+      return Err(RomError::TooShort(file.len()));
+    }
+    let sides: Vec<Vec<u8>> = data.chunks_exact(side_size)
+      .map(|side| if format == DiskFormat::Qd {Self::side_from_qd(side)} else {side.to_vec()})
+      .collect();
+    for (i, side) in sides.iter().enumerate() {
+      if !side.starts_with(DISK_INFO_MAGIC) {
+        return Err(RomError::Malformed(format!("FDS side {} has no disk info block", i)));
+      }
+    }
+    Ok(Self {
+      format,
+      sides,
+    })
+  }
+
+  fn side_from_qd(qd_side: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = block_len(&qd_side[pos..], file_size) {
+      if pos + len > qd_side.len() {
+        break;
+      }
+      file_size = file_header_size(&qd_side[pos..(pos + len)]);
+      side.extend_from_slice(&qd_side[pos..(pos + len)]);
+      pos += len + 2;
+      if pos >= qd_side.len() {
+        break;
+      }
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+  }
+
+  fn side_to_qd(side: &[u8]) -> Vec<u8> {
+    let mut qd_side = Vec::with_capacity(QD_SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = block_len(&side[pos..], file_size) {
+      if pos + len > side.len() {
+        break;
+      }
+      let block = &side[pos..(pos + len)];
+      file_size = file_header_size(block);
+      let mut marked = vec![0x80];
+      marked.extend_from_slice(block);
+      qd_side.extend_from_slice(block);
+      qd_side.extend_from_slice(&crc(&marked).to_le_bytes());
+      pos += len;
+    }
+    qd_side.resize(QD_SIDE_SIZE, 0);
+    qd_side
+  }
+
+  /// Image in the layout it was loaded from.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut file = Vec::new();
+    match self.format {
+      DiskFormat::Fds => {
+        file.extend_from_slice(FDS_MAGIC);
+        file.push(self.sides.len() as u8);
+        file.resize(FDS_HEADER_SIZE, 0);
+        self.sides.iter().for_each(|side| file.extend_from_slice(side));
+      },
+      DiskFormat::FdsHeaderless => {
+        self.sides.iter().for_each(|side| file.extend_from_slice(side));
+      },
+      DiskFormat::Qd => {
+        self.sides.iter().for_each(|side| file.extend_from_slice(&Self::side_to_qd(side)));
+      },
+    }
+    file
+  }
+}
@@ -0,0 +1,72 @@
+const CRC32_POLY: u32 = 0xEDB8_8320;
+
+fn crc32_table() -> [u32; 256] {
+  let mut table = [0u32; 256];
+  for (n, entry) in table.iter_mut().enumerate() {
+    let mut c = n as u32;
+    for _ in 0..8 {
+      c = if c & 1 != 0 {CRC32_POLY ^ (c >> 1)} else {c >> 1};
+    }
+    *entry = c;
+  }
+  table
+}
+
+/// CRC-32 (IEEE 802.3), same as zip/No-Intro/nes20db.
+pub fn crc32(data: &[u8]) -> u32 {
+  let table = crc32_table();
+  let mut crc: u32 = 0xFFFF_FFFF;
+  for byte in data {
+    crc = table[((crc ^ (*byte as u32)) & 0xFF) as usize] ^ (crc >> 8);
+  }
+  !crc
+}
+
+/// SHA-1 digest, 20 bytes big endian.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+  let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+
+  let mut msg = data.to_vec();
+  let bit_len = (data.len() as u64).wrapping_mul(8);
+  msg.push(0x80);
+  while msg.len() % 64 != 56 {
+    msg.push(0);
+  }
+  msg.extend_from_slice(&bit_len.to_be_bytes());
+
+  for chunk in msg.chunks(64) {
+    let mut w = [0u32; 80];
+    for i in 0..16 {
+      w[i] = u32::from_be_bytes([chunk[i * 4], chunk[i * 4 + 1], chunk[i * 4 + 2], chunk[i * 4 + 3]]);
+    }
+    for i in 16..80 {
+      w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+    for (i, wi) in w.iter().enumerate() {
+      let (f, k) = match i {
+        0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+        20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+        40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+        _ => (b ^ c ^ d, 0xCA62_C1D6),
+      };
+      let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+      e = d;
+      d = c;
+      c = b.rotate_left(30);
+      b = a;
+      a = temp;
+    }
+    h[0] = h[0].wrapping_add(a);
+    h[1] = h[1].wrapping_add(b);
+    h[2] = h[2].wrapping_add(c);
+    h[3] = h[3].wrapping_add(d);
+    h[4] = h[4].wrapping_add(e);
+  }
+
+  let mut digest = [0u8; 20];
+  for (i, v) in h.iter().enumerate() {
+    digest[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+  }
+  digest
+}
@@ -0,0 +1,238 @@
+use crate::nes::cartridge::{RomError, TimingType};
+
+pub const NSF_MAGIC: &[u8] = b"NESM\x1A";
+pub const NSFE_MAGIC: &[u8] = b"NSFE";
+const NSF_HEADER_SIZE: usize = 0x80;
+
+/// Default play rates when an NSFe has no RATE chunk, in microseconds
+const PLAY_SPEED_NTSC: u16 = 16639;
+const PLAY_SPEED_PAL: u16 = 19997;
+
+pub const CHIP_VRC6: u8 = 0b0000_0001;
+pub const CHIP_VRC7: u8 = 0b0000_0010;
+pub const CHIP_FDS: u8 = 0b0000_0100;
+pub const CHIP_MMC5: u8 = 0b0000_1000;
+pub const CHIP_N163: u8 = 0b0001_0000;
+pub const CHIP_S5B: u8 = 0b0010_0000;
+
+const CHIP_NAMES: [(u8, &str); 6] = [
+  (CHIP_VRC6, "VRC6"),
+  (CHIP_VRC7, "VRC7"),
+  (CHIP_FDS, "FDS"),
+  (CHIP_MMC5, "MMC5"),
+  (CHIP_N163, "Namco 163"),
+  (CHIP_S5B, "Sunsoft 5B"),
+];
+
+/// Expansion chips the player can emulate
+pub const SUPPORTED_CHIPS: u8 = CHIP_FDS;
+
+#[derive(Debug, Clone)]
+pub struct NsfInfo {
+  pub title: String,
+  pub artist: String,
+  pub copyright: String,
+  pub track_labels: Vec<String>,
+
+  pub total_songs: u8,
+  /// 0-based
+  pub starting_song: u8,
+  pub load_addr: u16,
+  pub init_addr: u16,
+  pub play_addr: u16,
+  pub play_speed_ntsc: u16,
+  pub play_speed_pal: u16,
+  pub bankswitch: [u8; 8],
+  pub timing_type: TimingType,
+  pub chips: u8,
+  pub data: Vec<u8>,
+}
+
+pub fn is_nsf(file: &[u8]) -> bool {
+  file.starts_with(NSF_MAGIC) || file.starts_with(NSFE_MAGIC)
+}
+
+fn le_u16(data: &[u8], pos: usize) -> u16 {
+  (data[pos] as u16) | ((data[pos + 1] as u16) << 8)
+}
+
+/// Null padded or null terminated string
+fn text(data: &[u8]) -> String {
+  let end = data.iter().position(|c| *c == 0).unwrap_or(data.len());
+  String::from_utf8_lossy(&data[..end]).trim().to_string()
+}
+
+fn timing(flags: u8) -> TimingType {
+  match flags & 0b0000_0011 {
+    0 => TimingType::NTSC_NES,
+    1 => TimingType::PAL_NES,
+    _ => TimingType::MUL_REG,
+  }
+}
+
+impl NsfInfo {
+  pub fn from_bytes(file: &[u8]) -> Result<Self, RomError> {
+    if file.starts_with(NSF_MAGIC) {
+      Self::from_nsf(file)
+    }
+    else if file.starts_with(NSFE_MAGIC) {
+      Self::from_nsfe(file)
+    }
+    else {
+      Err(RomError::BadMagic)
+    }
+  }
+
+  fn from_nsf(file: &[u8]) -> Result<Self, RomError> {
+    if file.len() <= NSF_HEADER_SIZE {
+      return Err(RomError::TooShort(file.len()));
+    }
+    let mut data = &file[NSF_HEADER_SIZE..];
+    // NSF2 program length, metadata follows the program
+    let data_len = (file[0x7D] as usize) | ((file[0x7E] as usize) << 8) | ((file[0x7F] as usize) << 16);
+    if file[0x05] >= 2 && data_len != 0 && data_len < data.len() {
+      data = &data[..data_len];
+    }
+    let mut bankswitch = [0u8; 8];
+    bankswitch.copy_from_slice(&file[0x70..0x78]);
+    Ok(Self {
+      title: text(&file[0x0E..0x2E]),
+      artist: text(&file[0x2E..0x4E]),
+      copyright: text(&file[0x4E..0x6E]),
+      track_labels: Vec::new(),
+
+      total_songs: file[0x06],
+      starting_song: file[0x07].saturating_sub(1),
+      load_addr: le_u16(file, 0x08),
+      init_addr: le_u16(file, 0x0A),
+      play_addr: le_u16(file, 0x0C),
+      play_speed_ntsc: le_u16(file, 0x6E),
+      play_speed_pal: le_u16(file, 0x78),
+      bankswitch,
+      timing_type: timing(file[0x7A]),
+      chips: file[0x7B],
+      data: data.to_vec(),
+    }).and_then(Self::check)
+  }
+
+  fn from_nsfe(file: &[u8]) -> Result<Self, RomError> {
+    let mut nsf = Self {
+      title: String::new(),
+      artist: String::new(),
+      copyright: String::new(),
+      track_labels: Vec::new(),
+
+      total_songs: 0,
+      starting_song: 0,
+      load_addr: 0,
+      init_addr: 0,
+      play_addr: 0,
+      play_speed_ntsc: PLAY_SPEED_NTSC,
+      play_speed_pal: PLAY_SPEED_PAL,
+      bankswitch: [0; 8],
+      timing_type: TimingType::NTSC_NES,
+      chips: 0,
+      data: Vec::new(),
+    };
+    let mut has_info = false;
+    let mut pos = NSFE_MAGIC.len();
+    while pos + 8 <= file.len() {
+      let len = u32::from_le_bytes([file[pos], file[pos + 1], file[pos + 2], file[pos + 3]]) as usize;
+      let id = &file[(pos + 4)..(pos + 8)];
+      pos += 8;
+      if pos + len > file.len() {
+        return Err(RomError::Malformed(format!("NSFe chunk {} is truncated", text(id))));
+      }
+      let chunk = &file[pos..(pos + len)];
+      pos += len;
+      match id {
+        b"INFO" => {
+          if chunk.len() < 8 {
+            return Err(RomError::Malformed("NSFe INFO chunk too short".to_string()));
+          }
+          nsf.load_addr = le_u16(chunk, 0);
+          nsf.init_addr = le_u16(chunk, 2);
+          nsf.play_addr = le_u16(chunk, 4);
+          nsf.timing_type = timing(chunk[6]);
+          nsf.chips = chunk[7];
+          nsf.total_songs = chunk.get(8).copied().unwrap_or(1);
+          nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+          has_info = true;
+        },
+        b"DATA" => nsf.data = chunk.to_vec(),
+        b"BANK" => {
+          let len = chunk.len().min(8);
+          nsf.bankswitch[..len].copy_from_slice(&chunk[..len]);
+        },
+        b"RATE" => {
+          if chunk.len() >= 2 {
+            nsf.play_speed_ntsc = le_u16(chunk, 0);
+          }
+          if chunk.len() >= 4 {
+            nsf.play_speed_pal = le_u16(chunk, 2);
+          }
+        },
+        b"auth" => {
+          let mut strings = chunk.split(|c| *c == 0).map(text);
+          nsf.title = strings.next().unwrap_or_default();
+          nsf.artist = strings.next().unwrap_or_default();
+          nsf.copyright = strings.next().unwrap_or_default();
+        },
+        b"tlbl" => {
+          nsf.track_labels = chunk.split(|c| *c == 0).map(text).collect();
+        },
+        b"NEND" => break,
+        // lowercase first letter: optional chunk
+        _ if id[0].is_ascii_lowercase() => (),
+        _ => return Err(RomError::UnsupportedFormat(format!("NSFe chunk {} is not supported", text(id)))),
+      }
+    }
+    if !has_info {
+      return Err(RomError::Malformed("NSFe without INFO chunk".to_string()));
+    }
+    nsf.check()
+  }
+
+  fn check(self) -> Result<Self, RomError> {
+    if self.data.is_empty() {
+      return Err(RomError::Malformed("NSF without program data".to_string()));
+    }
+    if self.total_songs == 0 {
+      return Err(RomError::Malformed("NSF without songs".to_string()));
+    }
+    if self.load_addr < 0x6000 || (self.load_addr < 0x8000 && self.chips & CHIP_FDS == 0) {
+      return Err(RomError::Malformed(format!("NSF load address {:#06x} out of range", self.load_addr)));
+    }
+    Ok(self)
+  }
+
+  /// Bank switched tunes have a non zero initial bank value
+  pub fn banked(&self) -> bool {
+    self.bankswitch.iter().any(|bank| *bank != 0)
+  }
+
+  pub fn fds(&self) -> bool {
+    self.chips & CHIP_FDS != 0
+  }
+
+  pub fn unsupported_chips(&self) -> Vec<&'static str> {
+    CHIP_NAMES.iter()
+      .filter(|(chip, _)| self.chips & chip & !SUPPORTED_CHIPS != 0)
+      .map(|(_, name)| *name)
+      .collect()
+  }
+
+  /// Play rate in microseconds, PAL only tunes keep their PAL rate
+  pub fn play_speed(&self) -> u16 {
+    let speed = if self.timing_type == TimingType::PAL_NES {self.play_speed_pal} else {self.play_speed_ntsc};
+    if speed != 0 {speed} else {PLAY_SPEED_NTSC}
+  }
+
+  /// `song` is 0-based
+  pub fn track_name(&self, song: u8) -> String {
+    match self.track_labels.get(song as usize) {
+      Some(label) if !label.is_empty() => format!("{}. {}", song + 1, label),
+      _ => format!("Track {}", song + 1),
+    }
+  }
+}
@@ -1,3 +1,6 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 
 use crate::nes::bus::Bus;
 
@@ -11,31 +14,90 @@ pub trait Clock<T> {
   }
 }
 
-pub struct SlaveClock {
-  pub div: u32,
-  pub dec: u32,
+/// One-shot events scheduled by the components
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+  /// Delayed effect of a $4017 write
+  FrameCounterWrite(u8),
+}
+
+/// Components due at the current master cycle
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Due {
+  pub ppu: bool,
+  pub cpu: bool,
+  pub apu: bool,
 }
 
-impl SlaveClock {
-  pub fn new(div: u32) -> Self{
-    SlaveClock {
-      div,
-      dec: div - 1,
+/// Runs the components on the master clock, each one every `period` master cycles,
+/// and the one-shot events at their master cycle.
+pub struct Scheduler {
+  master: u64,
+  cpu_period: u64,
+  ppu_period: u64,
+  apu_period: u64,
+  next_cpu: u64,
+  next_ppu: u64,
+  next_apu: u64,
+  /// Events by master cycle, then in scheduling order
+  events: BinaryHeap<Reverse<(u64, u64, Event)>>,
+  event_count: u64,
+}
+
+impl Scheduler {
+  /// `dividers` are the CPU, PPU and APU periods in master cycles
+  pub fn new(dividers: (u32, u32, u32)) -> Self {
+    let (cpu_period, ppu_period, apu_period) = (dividers.0 as u64, dividers.1 as u64, dividers.2 as u64);
+    Self {
+      master: 0,
+      cpu_period,
+      ppu_period,
+      apu_period,
+      next_cpu: cpu_period,
+      next_ppu: ppu_period,
+      next_apu: apu_period,
+      events: BinaryHeap::new(),
+      event_count: 0,
     }
   }
 
-  pub fn reset(&mut self) {
-    self.dec = self.div;
-  }
+  /// Moves to the next master cycle where something happens
+  pub fn advance(&mut self) -> Due {
+    let mut next = self.next_cpu.min(self.next_ppu).min(self.next_apu);
+    if let Some(Reverse((at, _, _))) = self.events.peek() {
+      next = next.min(*at);
+    }
+    self.master = next;
 
-  pub fn tick(&mut self) -> bool {
-    if self.dec == 0 {
-      self.dec = self.div - 1;
-      true
+    let due = Due {
+      ppu: self.next_ppu == next,
+      cpu: self.next_cpu == next,
+      apu: self.next_apu == next,
+    };
+    if due.ppu {
+      self.next_ppu += self.ppu_period;
     }
-    else {
-      self.dec -= 1;
-      false
+    if due.cpu {
+      self.next_cpu += self.cpu_period;
+    }
+    if due.apu {
+      self.next_apu += self.apu_period;
+    }
+    due
+  }
+
+  /// Schedules `event` `cpu_cycles` CPU cycles from now, it runs before the components
+  pub fn schedule_cpu_cycles(&mut self, cpu_cycles: u32, event: Event) {
+    let at = self.master + (cpu_cycles as u64) * self.cpu_period;
+    self.events.push(Reverse((at, self.event_count, event)));
+    self.event_count += 1;
+  }
+
+  /// Next event due at the current master cycle
+  pub fn pop_event(&mut self) -> Option<Event> {
+    match self.events.peek() {
+      Some(Reverse((at, _, _))) if *at <= self.master => self.events.pop().map(|Reverse((_, _, event))| event),
+      _ => None,
     }
   }
 }
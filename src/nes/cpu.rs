@@ -1,4 +1,5 @@
 mod opcode;
+mod cycle;
 use std::fmt;
 
 use crate::nes::{
@@ -20,6 +21,11 @@ use opcode::*;
 
 const STACK_ADDR: u16 = 0x0100;
 const INDIRECT_BUG_JMP: bool = true;
+const NMI_VECTOR: u16 = 0xFFFA;
+/// Bits of A that stay set in XAA and immediate LAX, they vary between consoles
+const XAA_MAGIC: u8 = 0xEE;
+const LXA_MAGIC: u8 = 0xFF;
+const IRQ_VECTOR: u16 = 0xFFFE;
 
 #[allow(non_snake_case)]
 impl StatusReg {
@@ -168,51 +174,75 @@ fn operand_lenght(instr: &InstructionInfo) -> u16 {
 pub struct CPU {
   reg: Reg,
   cycles_frame: u32,
-  cycles_instr: u32,
-  cycles_since_last_exec: u32,
+  /// Cycle of the current instruction, 0 between instructions
+  step: u8,
+  /// Cycles the CPU is halted for by a DMA
+  stall: u32,
   operand: [u8; 2],
   addr_abs: u16,
-  addr_rel: i8,
+  /// Indexed address before the index is added, to detect page crossings
+  addr_base: u16,
+  /// Vector of the interrupt being serviced
+  interrupt: Option<u16>,
   instr: InstructionInfo,
-  op_len: u16,
-  as_jump: bool,
   have_bcd: bool,
   instr_op_load: bool,
+  irq_line: bool,
+  /// Set by the KIL opcodes, only a reset restarts the CPU
+  jammed: bool,
   pub debug: bool,
 }
 
 impl Clock<bool> for CPU {
+  /// Runs a single cycle, with its bus access, returns true when an instruction starts
   fn tick(&mut self, bus: &mut Bus) -> bool{
-    self.cycles_since_last_exec += 1;
     self.cycles_frame += 1;
-    //if bus.get_oam_dma_state() {
-      //print!("-");
-    //}
-    if self.cycles_since_last_exec >= self.cycles_instr {
-      if bus.ppu_mem.get_nmi_output() && bus.ppu_mem.read_status() & 0b1000_0000 != 0 {
-        bus.ppu_mem.nmi();
-        self.cycles_instr += 2;
-        self.NMI(bus);
+    if self.jammed {
+      return false;
+    }
+    if self.stall > 0 {
+      self.stall -= 1;
+      return false;
+    }
+    if self.step > 0 {
+      self.step += 1;
+      let interrupt = self.interrupt.is_some();
+      if self.exec_cycle(bus) {
+        debug_assert!(interrupt || self.jammed || self.step as u32 == self.expected_cycles(),
+          "{} took {} cycles instead of {}", self.instr, self.step, self.expected_cycles());
+        self.step = 0;
+      }
+      return false;
+    }
+
+    if bus.dmc_dma_pending() {
+      self.stall = bus.dmc_dma() - 1;
+      false
+    }
+    else if bus.ppu_mem.get_nmi_output() && bus.ppu_mem.read_status() & 0b1000_0000 != 0 {
+      bus.ppu_mem.nmi();
+      self.start_interrupt(bus, NMI_VECTOR);
+      false
+    }
+    else if self.irq_line && !self.reg.P.get_I() && !bus.get_oam_dma_state() {
+      self.start_interrupt(bus, IRQ_VECTOR);
+      false
+    }
+    else if bus.get_oam_dma_state() {
+      if self.cycles_frame % 2 == 0 {
+        bus.oam_dma_tick();
+        self.stall = 1;
       }
-      else if bus.get_oam_dma_state() {
-        if self.cycles_frame % 2 == 0 {
-          bus.oam_dma_tick();
-          self.cycles_since_last_exec = 0;
-          self.cycles_instr = 2;
-        }
+      false
+    }
+    else {
+      if self.debug {
+        self.debug_next_instr(bus);
       }
       else {
-        if self.debug {
-          self.debug_next_instr(bus);
-        }
-        else {
-          self.next_instr(bus);
-        }
-        self.cycles_since_last_exec = 0;
+        self.next_instr(bus);
       }
       true
-    } else {
-      false
     }
   }
 }
@@ -222,22 +252,26 @@ impl CPU {
     Self {
       reg: Reg::new(),
       cycles_frame: 0,
-      cycles_instr: 0,
-      cycles_since_last_exec: 0,
+      step: 0,
+      stall: 0,
       operand: [0, 0],
       addr_abs: 0,
-      addr_rel: 0,
+      addr_base: 0,
+      interrupt: None,
       instr: InstructionInfo::new(),
-      op_len: 0,
-      as_jump: false,
       have_bcd: false,
       instr_op_load: false,
+      irq_line: false,
+      jammed: false,
       debug: false,
     }
   }
 
+  /// The state is saved between instructions
   pub(super) fn load_reg_state(&mut self, reg: &Reg) {
     self.reg = *reg;
+    self.step = 0;
+    self.stall = 0;
   }
 
   pub(super) fn save_reg_state(&self) -> Reg {
@@ -255,8 +289,9 @@ impl CPU {
   pub fn reset(&mut self, bus: &mut Bus) {
     self.reg.reset();
     self.cycles_frame = 0;
-    self.cycles_instr = 2;
-    self.cycles_since_last_exec = 0;
+    self.step = 0;
+    self.stall = 7;
+    self.jammed = false;
 
     self.reg.PC = ((bus.read(0xFFFD) as u16) << 8) + bus.read(0xFFFC) as u16;
     bus.write(0xFE, 0xFF);
@@ -264,27 +299,20 @@ impl CPU {
     println!("PC : {:#04x}", self.reg.PC);
   }
 
+  /// Fetches the opcode, the rest of the instruction runs on the next cycles
   pub fn next_instr(&mut self, bus: &mut Bus) {
-    self.cycles_instr = 0;
-    self.read_instr(bus);
-    self.exec_instr(bus);
+    let opcode = self.fetch(bus);
+    self.instr = opcode::opcode_to_enum(opcode);
+    self.instr_op_load = false;
+    self.step = 1;
   }
 
   pub fn set_debug(&mut self, debug: bool) {
     self.debug = debug;
   }
 
-  fn read_instr(&mut self, bus: &mut Bus) {
-    let opcode = bus.read(self.reg.PC as usize);
-    self.instr = opcode::opcode_to_enum(opcode);
-    self.op_len = operand_lenght(&self.instr);
-    if self.op_len >= 1 {
-      self.operand[0] = bus.read((self.reg.PC.wrapping_add(1)).into());
-    }
-    if self.op_len == 2 {
-      self.operand[1] = bus.read((self.reg.PC.wrapping_add(2)).into());
-    }
-    self.reg.PC = self.reg.PC.wrapping_add(self.op_len + 1);
+  pub fn set_irq(&mut self, irq: bool) {
+    self.irq_line = irq;
   }
 }
 
@@ -292,20 +320,20 @@ impl CPU {
   pub fn debug_reset(&mut self, bus: &mut Bus) {
     self.reg.reset();
     self.reset(bus);
-    self.cycles_instr = 7;
 
     self.reg.PC = 0xC000;
   }
 
   pub fn debug_next_instr(&mut self, bus: &mut Bus) {
-    self.cycles_instr = 0;
-    print!("{:#06x}", self.reg.PC);
-    self.read_instr(bus);
+    let pc = self.reg.PC;
+    self.next_instr(bus);
+    print!("{:#06x}", pc);
     print!(" {}[{:#04x}]", self.instr.instr, self.instr.opcode);
-    if self.op_len >= 1 {
-      print!(" {:#04x}", self.operand[0]);
-      if self.op_len == 2 {
-        print!(" {:#04x}", self.operand[1]);
+    let op_len = operand_lenght(&self.instr);
+    if op_len >= 1 {
+      print!(" {:#04x}", bus.peek(pc.wrapping_add(1).into()));
+      if op_len == 2 {
+        print!(" {:#04x}", bus.peek(pc.wrapping_add(2).into()));
       } else {
         print!("\t");
       }
@@ -313,11 +341,6 @@ impl CPU {
       print!("\t");
     }
     print!("\t{:#04x} {:#04x} {:#04x} {:#04x} {:#04x}", self.reg.A, self.reg.X, self.reg.Y, self.reg.P.value, self.reg.S);
-    self.exec_instr(bus);
-    if self.as_jump {
-      //print!(" AS BRANCH to {:#04x}", self.reg.PC);
-    }
-    //println!("");
   }
 
   pub fn debug_print_stack(&mut self, bus: &mut Bus) {
@@ -331,9 +354,6 @@ impl CPU {
 #[allow(non_snake_case)]
 impl CPU {
   fn exec_instr(&mut self, bus: &mut Bus) {
-    self.cycles_instr = self.instr.cycles;
-    self.handle_adressing_mode(bus);
-    self.as_jump = false;
     match self.instr.instr {
       //Logical and arithmetic commands:
       Instruction::ORA => {self.ORA(bus)},
@@ -368,25 +388,7 @@ impl CPU {
       Instruction::TYA => {self.TYA(bus)},
       Instruction::TSX => {self.TSX(bus)},
       Instruction::TXS => {self.TXS(bus)},
-      Instruction::PLA => {self.PLA(bus)},
-      Instruction::PHA => {self.PHA(bus)},
-      Instruction::PLP => {self.PLP(bus)},
-      Instruction::PHP => {self.PHP(bus)},
-      //Jump commands:
-      Instruction::BPL => {self.BPL(bus)},
-      Instruction::BMI => {self.BMI(bus)},
-      Instruction::BVC => {self.BVC(bus)},
-      Instruction::BVS => {self.BVS(bus)},
-      Instruction::BCC => {self.BCC(bus)},
-      Instruction::BCS => {self.BCS(bus)},
-      Instruction::BNE => {self.BNE(bus)},
-      Instruction::BEQ => {self.BEQ(bus)},
-      Instruction::JMP => {self.JMP(bus)},
-      Instruction::JSR => {self.JSR(bus)},
-      Instruction::RTS => {self.RTS(bus)},
-      //Interrupt commands:
-      Instruction::RTI => {self.RTI(bus)},
-      //Instruction::BRK => {self.BRK(bus)},
+      //Stack, jump and interrupt commands are sequenced by `exec_cycle`
       //Flags commands:
       Instruction::CLC => {self.CLC(bus)},
       Instruction::SEC => {self.SEC(bus)},
@@ -395,19 +397,30 @@ impl CPU {
       Instruction::CLI => {self.CLI(bus)},
       Instruction::SEI => {self.SEI(bus)},
       Instruction::CLV => {self.CLV(bus)},
-      Instruction::NOP => (),
+      // the unofficial NOPs still read their operand
+      Instruction::NOP => self.load_operand(bus),
       //Illegal commands:
       Instruction::SLO => self.SLO(bus),
       Instruction::RLA => self.RLA(bus),
       Instruction::SRE => self.SRE(bus),
       Instruction::RRA => self.RRA(bus),
       Instruction::SAX => self.SAX(bus),
-      Instruction::LAX => self.LAX(bus),
+      Instruction::LAX => match self.instr.mode {
+        OpMode::IMM => self.LXA(bus),
+        _ => self.LAX(bus),
+      },
       Instruction::DCP => self.DCP(bus),
       Instruction::ISC => self.ISC(bus),
       Instruction::ANC => self.ANC(bus),
       Instruction::ALR => self.ALR(bus),
       Instruction::ARR => self.ARR(bus),
+      Instruction::AXS => self.AXS(bus),
+      Instruction::XAA => self.XAA(bus),
+      Instruction::LAS => self.LAS(bus),
+      Instruction::AHX => self.AHX(bus),
+      Instruction::SHX => self.SHX(bus),
+      Instruction::SHY => self.SHY(bus),
+      Instruction::TAS => self.TAS(bus),
       _ => {println!("not implemented yet: {}", self.instr.instr)}
     }
   }
@@ -430,78 +443,6 @@ impl CPU {
     self.instr_op_load = true;
   }
 
-  fn handle_adressing_mode(&mut self, bus: &mut Bus) {
-    self.instr_op_load = false;
-    match self.instr.mode {
-      OpMode::ACC => {self.operand[0] = self.reg.A},
-      OpMode::IMP => {},
-      OpMode::IMM => {},
-      OpMode::ZP0 => {
-        self.addr_abs = self.operand[0].into();
-      },
-      OpMode::ZPX => {
-        self.addr_abs = self.operand[0].wrapping_add(self.reg.X).into();
-      },
-      OpMode::ZPY => {
-        self.addr_abs = self.operand[0].wrapping_add(self.reg.Y).into();
-      },
-      OpMode::IZX => {
-        let ind: u8 = self.operand[0].wrapping_add(self.reg.X);
-        let addr1 = bus.read(ind.into());
-        let addr2 = bus.read(ind.wrapping_add(1).into());
-        self.addr_abs = ((addr2 as u16) << 8) + addr1 as u16;
-        //print!("IZX:{:#04x}={:#04x},{:#04x}={:#04x},{:#04x}", ind, addr1
-          //, ind.wrapping_add(1), addr2, self.operand[0]);
-      },
-      OpMode::IZY => {
-        let ind: u8 = self.operand[0].into();
-        let addr1 = bus.read(ind.into());
-        let addr2 = bus.read(ind.wrapping_add(1).into());
-        self.addr_abs = (((addr2 as u16) << 8) + addr1 as u16).wrapping_add(self.reg.Y as u16);
-        if self.instr.cycle_inc_pbc && self.addr_abs.wrapping_shr(8) != addr2 as u16 {
-          self.cycles_instr += 1;
-        }
-      },
-      OpMode::ABS => {
-        self.addr_abs = ((self.operand[1] as u16) << 8) + self.operand[0] as u16;
-      },
-      OpMode::ABX => {
-        self.addr_abs = (((self.operand[1] as u16) << 8) + self.operand[0] as u16)
-          .wrapping_add(self.reg.X as u16);
-        if self.instr.cycle_inc_pbc
-            && self.addr_abs.wrapping_shr(8) != self.operand[1] as u16 {
-          self.cycles_instr += 1;
-        }
-      },
-      OpMode::ABY => {
-        self.addr_abs = (((self.operand[1] as u16) << 8) + self.operand[0] as u16)
-          .wrapping_add(self.reg.Y as u16);
-        if self.instr.cycle_inc_pbc
-            && self.addr_abs.wrapping_shr(8) != self.operand[1] as u16 {
-          self.cycles_instr += 1;
-        }
-      },
-      OpMode::IND => {
-        let ind: usize = ((self.operand[1] as usize) << 8) + self.operand[0] as usize;
-        let addr1 = bus.read(ind);
-        let mut addr2 = bus.read(ind + 1);
-        if INDIRECT_BUG_JMP && self.operand[0] == 0xFF {
-          addr2 = bus.read((self.operand[1] as usize) << 8);
-        }
-        self.addr_abs = ((addr2 as u16) << 8) + addr1 as u16;
-      },
-      OpMode::REL => {
-        let offset: i8 = match self.operand[0] {
-          0..=127 => {self.operand[0].try_into().unwrap()}
-          128..=255 => {-1 as i8 - ((255 - self.operand[0]) as i8) }
-        };
-        self.addr_abs = self.reg.PC.wrapping_add(offset as u16);
-        //print!(" {:#06x} {} {:#06x}", self.reg.PC, offset, self.addr_abs);
-        self.addr_rel = offset;
-      },
-    }
-  }
-
   //Logical and arithmetic commands:
   fn ADC(&mut self, bus: &mut Bus) {
     self.load_operand(bus);
@@ -783,176 +724,6 @@ impl CPU {
     self.reg.S = self.reg.X;
   }
 
-  fn PHA(&mut self, bus: &mut Bus) {
-    let addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(addr.into(), self.reg.A);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-  }
-
-  fn PHP(&mut self, bus: &mut Bus) {
-    let addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(addr.into(), self.reg.P.set_B(3));
-    self.reg.S = self.reg.S.wrapping_sub(1);
-  }
-
-  fn PLA(&mut self, bus: &mut Bus) {
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let addr = STACK_ADDR + self.reg.S as u16;
-    self.reg.A = bus.read(addr.into());
-    self.reg.P.set_Z(if self.reg.A == 0 {true} else {false});
-    self.reg.P.set_N(if self.reg.A & 0b1000_0000 != 0 {true} else {false});
-  }
-
-  fn PLP(&mut self, bus: &mut Bus) {
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let addr = STACK_ADDR + self.reg.S as u16;
-    self.reg.P.set_value(bus.read(addr.into()));
-  }
-
-  //Jump / Branch commands:
-  fn branch(&mut self, _bus: &mut Bus) {
-    self.cycles_instr += 1;
-    if self.instr.cycle_inc_pbc && self.reg.PC.wrapping_shr(8) !=
-        self.addr_abs.wrapping_shr(8) {
-      self.cycles_instr += 1;
-    }
-    self.reg.PC = self.addr_abs;
-    self.as_jump = true;
-  }
-
-  fn BCS(&mut self, bus: &mut Bus) {
-    if self.reg.P.get_C() {self.branch(bus);}
-  }
-
-  fn BCC(&mut self, bus: &mut Bus) {
-    if !self.reg.P.get_C() {self.branch(bus);}
-  }
-
-  fn BEQ(&mut self, bus: &mut Bus) {
-    if self.reg.P.get_Z() {self.branch(bus);}
-  }
-
-  fn BNE(&mut self, bus: &mut Bus) {
-    if !self.reg.P.get_Z() {self.branch(bus);}
-  }
-
-  fn BMI(&mut self, bus: &mut Bus) {
-    if self.reg.P.get_N() {self.branch(bus);}
-  }
-
-  fn BPL(&mut self, bus: &mut Bus) {
-    if !self.reg.P.get_N() {self.branch(bus);}
-  }
-
-  fn BVS(&mut self, bus: &mut Bus) {
-    if self.reg.P.get_V() {self.branch(bus);}
-  }
-
-  fn BVC(&mut self, bus: &mut Bus) {
-    if !self.reg.P.get_V() {self.branch(bus);}
-  }
-
-  fn JMP(&mut self, _bus: &mut Bus) {
-    self.reg.PC = self.addr_abs;
-    self.as_jump = true;
-  }
-
-  fn JSR(&mut self, bus: &mut Bus) {
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    let msb: u8 = (self.reg.PC.wrapping_shr(8)).try_into().unwrap();
-    let lsb: u8 = (self.reg.PC & 0xFF).try_into().unwrap();
-
-    bus.write(stack_addr.into(), msb);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(stack_addr.into(), lsb.wrapping_sub(1));
-
-    self.reg.S = self.reg.S.wrapping_sub(1);
-    self.reg.PC = self.addr_abs;
-    self.as_jump = true;
-  }
-
-  fn RTS(&mut self, bus: &mut Bus) {
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    let mut lsb = bus.read(stack_addr.into());
-
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    let msb = bus.read(stack_addr.into());
-
-    lsb = lsb.wrapping_add(1);
-    self.reg.PC = ((msb as u16) << 8) + lsb as u16;
-    self.as_jump = true;
-  }
-
-  //Interrupt commands:
-  fn BRK(&mut self, bus: &mut Bus) { // TODO
-    let msb: u8 = (self.reg.PC.wrapping_shr(8)) as u8;
-    let lsb: u8 = (self.reg.PC & 0xFF) as u8;
-
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    self.reg.P.set_B(1);
-    bus.write(stack_addr.into(), msb);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(stack_addr.into(), lsb);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(stack_addr.into(), self.reg.P.value);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-    self.reg.PC = (bus.read(0xFFFE) as u16) | (bus.read(0xFFFF) as u16).wrapping_shl(8);
-  }
-
-  fn IRQ(&mut self, bus: &mut Bus) { // TODO
-    let addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(addr.into(), self.reg.P.set_B(2));
-  }
-
-  fn NMI(&mut self, bus: &mut Bus) { // TODO
-    let msb: u8 = (self.reg.PC.wrapping_shr(8)) as u8;
-    let lsb: u8 = (self.reg.PC & 0xFF) as u8;
-
-    //println!(" NMI({:#06x}) {:#04x} ", self.reg.PC, self.reg.P.value);
-    //println!("BEFORE:");
-    //self.debug_print_stack(bus);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    self.reg.P.set_B(2);
-    bus.write(stack_addr.into(), msb);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(stack_addr.into(), lsb);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    bus.write(stack_addr.into(), self.reg.P.value);
-    self.reg.S = self.reg.S.wrapping_sub(1);
-    //println!("AFTER:");
-    //self.debug_print_stack(bus);
-    self.reg.PC = (bus.read(0xFFFA) as u16) | (bus.read(0xFFFB) as u16).wrapping_shl(8);
-  }
-
-  fn RTI(&mut self, bus: &mut Bus) {
-    //self.debug_print_stack(bus);
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    self.reg.P.set_value(bus.read(stack_addr.into()));
-
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    let lsb = bus.read(stack_addr.into());
-
-    self.reg.S = self.reg.S.wrapping_add(1);
-    let stack_addr = STACK_ADDR + self.reg.S as u16;
-    let msb = bus.read(stack_addr.into());
-
-    self.reg.PC = ((msb as u16) << 8) + lsb as u16;
-    self.as_jump = true;
-  }
-
   //Flags commands:
   fn CLC(&mut self, _bus: &mut Bus) {
     self.reg.P.set_C(false);
@@ -1018,14 +789,79 @@ impl CPU {
   //Imm + impl
   fn ANC(&mut self, bus: &mut Bus) {
     self.AND(bus);
-    self.reg.P.set_C(if self.operand[0] & 0b1000_0000 != 0 {true} else {false});
+    self.reg.P.set_C(if self.reg.A & 0b1000_0000 != 0 {true} else {false});
   }
   fn ALR(&mut self, bus: &mut Bus) {
     self.AND(bus);
-    self.LSR(bus);
+    self.reg.P.set_C(if self.reg.A & 0b0000_0001 != 0 {true} else {false});
+    self.reg.A = self.reg.A.wrapping_shr(1);
+    self.reg.P.set_Z(if self.reg.A == 0 {true} else {false});
+    self.reg.P.set_N(false);
   }
+  /// C is bit 6 of the result and V is bit 6 xor bit 5
   fn ARR(&mut self, bus: &mut Bus) {
     self.AND(bus);
-    self.ROR(bus);
+    let carry = if self.reg.P.get_C() {0b1000_0000} else {0};
+    self.reg.A = self.reg.A.wrapping_shr(1) | carry;
+    self.reg.P.set_Z(if self.reg.A == 0 {true} else {false});
+    self.reg.P.set_N(if self.reg.A & 0b1000_0000 != 0 {true} else {false});
+    self.reg.P.set_C(if self.reg.A & 0b0100_0000 != 0 {true} else {false});
+    self.reg.P.set_V(if ((self.reg.A >> 6) ^ (self.reg.A >> 5)) & 1 != 0 {true} else {false});
+  }
+  /// X = (A & X) - operand, the flags are set like CMP
+  fn AXS(&mut self, bus: &mut Bus) {
+    self.load_operand(bus);
+    let r = self.reg.A & self.reg.X;
+    self.reg.P.set_C(if r >= self.operand[0] {true} else {false});
+    self.reg.X = r.wrapping_sub(self.operand[0]);
+    self.reg.P.set_Z(if self.reg.X == 0 {true} else {false});
+    self.reg.P.set_N(if self.reg.X & 0b1000_0000 != 0 {true} else {false});
+  }
+  /// A = (A | magic) & X & operand
+  fn XAA(&mut self, bus: &mut Bus) {
+    self.load_operand(bus);
+    self.reg.A = (self.reg.A | XAA_MAGIC) & self.reg.X & self.operand[0];
+    self.reg.P.set_Z(if self.reg.A == 0 {true} else {false});
+    self.reg.P.set_N(if self.reg.A & 0b1000_0000 != 0 {true} else {false});
+  }
+  /// Immediate LAX (LXA) also goes through A | magic
+  fn LXA(&mut self, bus: &mut Bus) {
+    self.load_operand(bus);
+    self.operand[0] &= self.reg.A | LXA_MAGIC;
+    self.LAX(bus);
+  }
+  fn LAS(&mut self, bus: &mut Bus) {
+    self.load_operand(bus);
+    let r = self.operand[0] & self.reg.S;
+    self.reg.A = r;
+    self.reg.X = r;
+    self.reg.S = r;
+    self.reg.P.set_Z(if r == 0 {true} else {false});
+    self.reg.P.set_N(if r & 0b1000_0000 != 0 {true} else {false});
+  }
+  //Unstable stores:
+  /// The stored value is ANDed with the high byte of the base address + 1,
+  /// and replaces the high byte of the target address when the page is crossed.
+  fn store_and_high(&mut self, bus: &mut Bus, value: u8) {
+    let high = (self.addr_base >> 8) as u8;
+    let value = value & high.wrapping_add(1);
+    let mut addr = self.addr_abs;
+    if addr & 0xFF00 != self.addr_base & 0xFF00 {
+      addr = ((value as u16) << 8) | (addr & 0x00FF);
+    }
+    bus.write(addr.into(), value);
+  }
+  fn AHX(&mut self, bus: &mut Bus) {
+    self.store_and_high(bus, self.reg.A & self.reg.X);
+  }
+  fn SHX(&mut self, bus: &mut Bus) {
+    self.store_and_high(bus, self.reg.X);
+  }
+  fn SHY(&mut self, bus: &mut Bus) {
+    self.store_and_high(bus, self.reg.Y);
+  }
+  fn TAS(&mut self, bus: &mut Bus) {
+    self.reg.S = self.reg.A & self.reg.X;
+    self.store_and_high(bus, self.reg.A & self.reg.X);
   }
 }
@@ -0,0 +1,432 @@
+use crate::nes::{
+  memory::{MemRead, MemWrite},
+  bus::Bus,
+};
+
+use super::{CPU, STACK_ADDR, INDIRECT_BUG_JMP, IRQ_VECTOR};
+use super::opcode::*;
+
+/// Cycle by cycle sequencing of the instructions, each cycle does exactly one bus access,
+/// including the dummy reads and writes of the real 6502.
+impl CPU {
+  /// Runs cycle `self.step` of the current instruction, returns true on its last cycle
+  pub(super) fn exec_cycle(&mut self, bus: &mut Bus) -> bool {
+    if self.interrupt.is_some() {
+      return self.interrupt_cycle(bus);
+    }
+    match self.instr.instr {
+      Instruction::BPL | Instruction::BMI | Instruction::BVC | Instruction::BVS
+        | Instruction::BCC | Instruction::BCS | Instruction::BNE
+        | Instruction::BEQ => self.branch_cycle(bus),
+      Instruction::JMP => self.jmp_cycle(bus),
+      Instruction::JSR => self.jsr_cycle(bus),
+      Instruction::RTS => self.rts_cycle(bus),
+      Instruction::RTI => self.rti_cycle(bus),
+      Instruction::PHA | Instruction::PHP => self.push_cycle(bus),
+      Instruction::PLA | Instruction::PLP => self.pull_cycle(bus),
+      Instruction::BRK => self.interrupt_cycle(bus),
+      Instruction::KIL => self.jam_cycle(bus),
+      _ => self.memory_cycle(bus),
+    }
+  }
+
+  /// Reads the byte at PC and moves past it
+  pub(super) fn fetch(&mut self, bus: &mut Bus) -> u8 {
+    let value = bus.read(self.reg.PC.into());
+    self.reg.PC = self.reg.PC.wrapping_add(1);
+    value
+  }
+
+  fn push(&mut self, bus: &mut Bus, value: u8) {
+    bus.write((STACK_ADDR + self.reg.S as u16).into(), value);
+    self.reg.S = self.reg.S.wrapping_sub(1);
+  }
+
+  /// Reads the top of the stack, `pull` moves the stack pointer first
+  fn stack_read(&mut self, bus: &mut Bus) -> u8 {
+    bus.read((STACK_ADDR + self.reg.S as u16).into())
+  }
+
+  fn pull(&mut self, bus: &mut Bus) -> u8 {
+    self.reg.S = self.reg.S.wrapping_add(1);
+    self.stack_read(bus)
+  }
+
+  /// NMI and IRQ replace the opcode fetch by a dummy read and run the BRK sequence
+  pub(super) fn start_interrupt(&mut self, bus: &mut Bus, vector: u16) {
+    bus.read(self.reg.PC.into());
+    self.interrupt = Some(vector);
+    self.step = 1;
+  }
+
+  /// Addressing cycles, `addr_abs` is set on the last one
+  fn address_cycle(&mut self, bus: &mut Bus) {
+    match (self.instr.mode, self.step) {
+      (OpMode::ZP0, 2) => {
+        self.operand[0] = self.fetch(bus);
+        self.addr_abs = self.operand[0].into();
+      },
+      (_, 2) => self.operand[0] = self.fetch(bus),
+      (OpMode::ZPX, 3) | (OpMode::ZPY, 3) | (OpMode::IZX, 3) => {
+        // the index is added while the base address is read
+        bus.read(self.operand[0].into());
+        let index = match self.instr.mode {
+          OpMode::ZPY => self.reg.Y,
+          _ => self.reg.X,
+        };
+        self.operand[0] = self.operand[0].wrapping_add(index);
+        self.addr_abs = self.operand[0].into();
+      },
+      (OpMode::ABS, 3) | (OpMode::ABX, 3) | (OpMode::ABY, 3) => {
+        self.operand[1] = self.fetch(bus);
+        self.addr_base = ((self.operand[1] as u16) << 8) + self.operand[0] as u16;
+        self.addr_abs = match self.instr.mode {
+          OpMode::ABX => self.addr_base.wrapping_add(self.reg.X as u16),
+          OpMode::ABY => self.addr_base.wrapping_add(self.reg.Y as u16),
+          _ => self.addr_base,
+        };
+      },
+      (OpMode::IZX, 4) => self.addr_abs = bus.read(self.operand[0].into()) as u16,
+      (OpMode::IZX, 5) => {
+        let addr2 = bus.read(self.operand[0].wrapping_add(1).into());
+        self.addr_abs |= (addr2 as u16) << 8;
+      },
+      (OpMode::IZY, 3) => self.addr_base = bus.read(self.operand[0].into()) as u16,
+      (OpMode::IZY, 4) => {
+        let addr2 = bus.read(self.operand[0].wrapping_add(1).into());
+        self.addr_base |= (addr2 as u16) << 8;
+        self.addr_abs = self.addr_base.wrapping_add(self.reg.Y as u16);
+      },
+      _ => (),
+    }
+  }
+
+  /// Cycle on which `addr_abs` is known
+  fn address_ready_step(&self) -> u8 {
+    match self.instr.mode {
+      OpMode::ZP0 => 2,
+      OpMode::ZPX | OpMode::ZPY | OpMode::ABS | OpMode::ABX | OpMode::ABY => 3,
+      OpMode::IZY => 4,
+      OpMode::IZX => 5,
+      _ => 1,
+    }
+  }
+
+  /// Instructions reading, writing or modifying their operand
+  fn memory_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.instr.mode {
+      OpMode::IMP | OpMode::ACC => {
+        bus.read(self.reg.PC.into());
+        self.exec_instr(bus);
+        return true;
+      },
+      OpMode::IMM => {
+        self.operand[0] = self.fetch(bus);
+        self.instr_op_load = true;
+        self.exec_instr(bus);
+        return true;
+      },
+      _ => (),
+    }
+
+    let ready = self.address_ready_step();
+    if self.step <= ready {
+      self.address_cycle(bus);
+      return false;
+    }
+    let mut data_step = ready + 1;
+    if let OpMode::ABX | OpMode::ABY | OpMode::IZY = self.instr.mode {
+      // the high byte isn't fixed yet, reads can skip the extra cycle when the page isn't crossed
+      if self.step == data_step {
+        let addr = (self.addr_base & 0xFF00) | (self.addr_abs & 0x00FF);
+        let value = bus.read(addr.into());
+        if self.instr.cycle_inc_pbc && addr == self.addr_abs {
+          self.operand[0] = value;
+          self.instr_op_load = true;
+          self.exec_instr(bus);
+          return true;
+        }
+        return false;
+      }
+      data_step += 1;
+    }
+
+    match (self.instr.instr.access(), self.step - data_step) {
+      (Access::ReadModifyWrite, 0) => {
+        self.load_operand(bus);
+        false
+      },
+      (Access::ReadModifyWrite, 1) => {
+        // the unmodified value is written back while the operation runs
+        bus.write(self.addr_abs.into(), self.operand[0]);
+        false
+      },
+      _ => {
+        self.exec_instr(bus);
+        true
+      },
+    }
+  }
+
+  /// Cycles the instruction takes according to the opcode table, with the page crossing penalties
+  pub(super) fn expected_cycles(&self) -> u32 {
+    let crossed = self.addr_base & 0xFF00 != self.addr_abs & 0xFF00;
+    if let OpMode::REL = self.instr.mode {
+      let taken = self.branch_taken();
+      self.instr.cycles + taken as u32 + (taken && crossed) as u32
+    }
+    else if self.instr.cycle_inc_pbc && crossed {
+      self.instr.cycles + 1
+    }
+    else {
+      self.instr.cycles
+    }
+  }
+
+  fn branch_taken(&self) -> bool {
+    match self.instr.instr {
+      Instruction::BPL => !self.reg.P.get_N(),
+      Instruction::BMI => self.reg.P.get_N(),
+      Instruction::BVC => !self.reg.P.get_V(),
+      Instruction::BVS => self.reg.P.get_V(),
+      Instruction::BCC => !self.reg.P.get_C(),
+      Instruction::BCS => self.reg.P.get_C(),
+      Instruction::BNE => !self.reg.P.get_Z(),
+      Instruction::BEQ => self.reg.P.get_Z(),
+      _ => false,
+    }
+  }
+
+  /// 2 cycles, +1 if taken, +1 more if the target is on another page
+  fn branch_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        self.operand[0] = self.fetch(bus);
+        self.addr_base = self.reg.PC;
+        self.addr_abs = self.reg.PC.wrapping_add(self.operand[0] as i8 as u16);
+        !self.branch_taken()
+      },
+      3 => {
+        bus.read(self.reg.PC.into());
+        let same_page = self.reg.PC & 0xFF00 == self.addr_abs & 0xFF00;
+        // PCL is updated first, PCH is fixed on the next cycle
+        self.reg.PC = (self.reg.PC & 0xFF00) | (self.addr_abs & 0x00FF);
+        same_page
+      },
+      _ => {
+        bus.read(self.reg.PC.into());
+        self.reg.PC = self.addr_abs;
+        true
+      },
+    }
+  }
+
+  fn jmp_cycle(&mut self, bus: &mut Bus) -> bool {
+    match (self.instr.mode, self.step) {
+      (_, 2) => {
+        self.operand[0] = self.fetch(bus);
+        false
+      },
+      (OpMode::ABS, _) => {
+        self.operand[1] = self.fetch(bus);
+        self.reg.PC = ((self.operand[1] as u16) << 8) + self.operand[0] as u16;
+        true
+      },
+      (_, 3) => {
+        self.operand[1] = self.fetch(bus);
+        false
+      },
+      (_, 4) => {
+        let ind: usize = ((self.operand[1] as usize) << 8) + self.operand[0] as usize;
+        self.addr_abs = bus.read(ind) as u16;
+        false
+      },
+      _ => {
+        // the pointer's high byte is read from the same page
+        let ind: usize = if INDIRECT_BUG_JMP && self.operand[0] == 0xFF {
+          (self.operand[1] as usize) << 8
+        }
+        else {
+          (((self.operand[1] as usize) << 8) + self.operand[0] as usize + 1) & 0xFFFF
+        };
+        self.addr_abs |= (bus.read(ind) as u16) << 8;
+        self.reg.PC = self.addr_abs;
+        true
+      },
+    }
+  }
+
+  /// The pushed return address is the last byte of the JSR
+  fn jsr_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        self.operand[0] = self.fetch(bus);
+        false
+      },
+      3 => {
+        self.stack_read(bus);
+        false
+      },
+      4 => {
+        self.push(bus, (self.reg.PC >> 8) as u8);
+        false
+      },
+      5 => {
+        self.push(bus, (self.reg.PC & 0xFF) as u8);
+        false
+      },
+      _ => {
+        self.operand[1] = bus.read(self.reg.PC.into());
+        self.reg.PC = ((self.operand[1] as u16) << 8) + self.operand[0] as u16;
+        true
+      },
+    }
+  }
+
+  fn rts_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        bus.read(self.reg.PC.into());
+        false
+      },
+      3 => {
+        self.stack_read(bus);
+        false
+      },
+      4 => {
+        self.reg.PC = self.pull(bus) as u16;
+        false
+      },
+      5 => {
+        self.reg.PC |= (self.pull(bus) as u16) << 8;
+        false
+      },
+      _ => {
+        self.fetch(bus);
+        true
+      },
+    }
+  }
+
+  fn rti_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        bus.read(self.reg.PC.into());
+        false
+      },
+      3 => {
+        self.stack_read(bus);
+        false
+      },
+      4 => {
+        let value = self.pull(bus);
+        self.reg.P.set_value(value);
+        false
+      },
+      5 => {
+        self.reg.PC = self.pull(bus) as u16;
+        false
+      },
+      _ => {
+        self.reg.PC |= (self.pull(bus) as u16) << 8;
+        true
+      },
+    }
+  }
+
+  /// PHA and PHP
+  fn push_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        bus.read(self.reg.PC.into());
+        false
+      },
+      _ => {
+        let value = match self.instr.instr {
+          Instruction::PHP => self.reg.P.set_B(3),
+          _ => self.reg.A,
+        };
+        self.push(bus, value);
+        true
+      },
+    }
+  }
+
+  /// PLA and PLP
+  fn pull_cycle(&mut self, bus: &mut Bus) -> bool {
+    match self.step {
+      2 => {
+        bus.read(self.reg.PC.into());
+        false
+      },
+      3 => {
+        self.stack_read(bus);
+        false
+      },
+      _ => {
+        let value = self.pull(bus);
+        match self.instr.instr {
+          Instruction::PLP => self.reg.P.set_value(value),
+          _ => {
+            self.reg.A = value;
+            self.reg.P.set_Z(self.reg.A == 0);
+            self.reg.P.set_N(self.reg.A & 0b1000_0000 != 0);
+          },
+        }
+        true
+      },
+    }
+  }
+
+  /// KIL reads the next byte and locks up the CPU
+  fn jam_cycle(&mut self, bus: &mut Bus) -> bool {
+    let pc = self.reg.PC.wrapping_sub(1);
+    self.fetch(bus);
+    self.jammed = true;
+    eprintln!("CPU jammed by opcode {:#04x} at {:#06x} (A:{:#04x} X:{:#04x} Y:{:#04x} P:{:#04x} S:{:#04x}), reset to restart it",
+      self.instr.opcode, pc, self.reg.A, self.reg.X, self.reg.Y, self.reg.P.value, self.reg.S);
+    true
+  }
+
+  /// BRK, NMI and IRQ: only BRK skips its padding byte and sets B in the pushed flags
+  fn interrupt_cycle(&mut self, bus: &mut Bus) -> bool {
+    let vector = self.interrupt.unwrap_or(IRQ_VECTOR);
+    match self.step {
+      2 => {
+        if self.interrupt.is_some() {
+          bus.read(self.reg.PC.into());
+        }
+        else {
+          self.fetch(bus);
+        }
+        false
+      },
+      3 => {
+        self.push(bus, (self.reg.PC >> 8) as u8);
+        false
+      },
+      4 => {
+        self.push(bus, (self.reg.PC & 0xFF) as u8);
+        false
+      },
+      5 => {
+        let value = match self.interrupt {
+          Some(_) => (self.reg.P.value | 0b0010_0000) & 0b1110_1111,
+          None => self.reg.P.set_B(3),
+        };
+        self.push(bus, value);
+        self.reg.P.set_I(true);
+        false
+      },
+      6 => {
+        self.reg.PC = bus.read(vector.into()) as u16;
+        false
+      },
+      _ => {
+        self.reg.PC |= (bus.read(vector.wrapping_add(1).into()) as u16) << 8;
+        self.interrupt = None;
+        true
+      },
+    }
+  }
+}
@@ -100,6 +100,16 @@ pub enum OpMode {
   REL,
 }
 
+/// How an instruction accesses its effective address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+  Read,
+  Write,
+  ReadModifyWrite,
+  /// Stack, jump and branch commands have their own cycle sequences
+  Control,
+}
+
 #[derive(Debug)]
 pub enum LegalityType{
   Legal = 0,
@@ -132,6 +142,24 @@ impl InstructionInfo {
   }
 }
 
+impl Instruction {
+  pub fn access(&self) -> Access {
+    match self {
+      Instruction::STA | Instruction::STX | Instruction::STY | Instruction::SAX
+        | Instruction::AHX | Instruction::SHY | Instruction::SHX | Instruction::TAS => Access::Write,
+      Instruction::ASL | Instruction::ROL | Instruction::LSR | Instruction::ROR
+        | Instruction::INC | Instruction::DEC | Instruction::SLO | Instruction::RLA
+        | Instruction::SRE | Instruction::RRA | Instruction::DCP | Instruction::ISC => Access::ReadModifyWrite,
+      Instruction::BPL | Instruction::BMI | Instruction::BVC | Instruction::BVS
+        | Instruction::BCC | Instruction::BCS | Instruction::BNE | Instruction::BEQ
+        | Instruction::BRK | Instruction::RTI | Instruction::JSR | Instruction::RTS
+        | Instruction::JMP | Instruction::PHA | Instruction::PHP | Instruction::PLA
+        | Instruction::PLP => Access::Control,
+      _ => Access::Read,
+    }
+  }
+}
+
 impl fmt::Display for Instruction {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     write!(f, "{:?}", self)
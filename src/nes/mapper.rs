@@ -1,6 +1,8 @@
 pub mod m000_nrom;
 pub mod m001_mmc1;
 pub mod m002_uxrom;
+pub mod m020_fds;
+pub mod m031_nsf;
 
 use std::fmt;
 use enum_dispatch::enum_dispatch;
@@ -9,12 +11,15 @@ use std::error::Error;
 use m000_nrom::Nrom;
 use m001_mmc1::MMC1;
 use m002_uxrom::Uxrom;
+use m020_fds::Fds;
+use m031_nsf::NsfPlayer;
 
 use crate::nes::{
   memory::{MemRead, MemWrite},
 };
 
 use crate::Cartridge;
+use crate::nes::cartridge::TRAINER_ADDR;
 
 #[derive(Debug)]
 struct ErrorMissingMapper {
@@ -38,7 +43,7 @@ impl fmt::Display for ErrorMissingMapper {
 impl Error for ErrorMissingMapper {}
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum MirroringType {
   Horizontal,
   Vertical,
@@ -70,13 +75,30 @@ pub enum MapperType {
   Nrom,
   MMC1,
   Uxrom,
+  Fds,
+  NsfPlayer,
 }
 
 #[enum_dispatch(MapperType)]
 pub trait Mapper: Clone + MemRead + MemWrite {
+  /// Polled by `Nes::tick` after every CPU cycle, drives the CPU IRQ line.
   fn irq_pending(&mut self) -> bool {
     false
   }
+  /// Called once per CPU cycle (on M2), for cycle counting IRQs
+  /// and M2 based filters (e.g. MMC3 A12 low time).
+  fn cpu_clock(&mut self) {}
+  /// Called every time the value on the PPU address bus changes,
+  /// either from a PPU fetch or from a $2006/$2007 access.
+  fn ppu_bus_change(&mut self, _addr: usize) {}
+  /// Expansion audio output, mixed by the APU in the same unit as its own channels.
+  fn expansion_audio(&self) -> f32 {
+    0.0
+  }
+  /// The board has the FDS audio channel ($4040-$409E, enabled from $4023)
+  fn fds_audio(&self) -> bool {
+    false
+  }
   fn mirroring(&self) -> MirroringType {
     MirroringType::Horizontal
   }
@@ -90,6 +112,14 @@ pub trait Mapper: Clone + MemRead + MemWrite {
     0
   }
   fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  /// CPU read of the cartridge space, `None` when the board doesn't drive the data bus
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    Some(self.read(addr))
+  }
+  /// `cpu_read` for the debugger, `None` for the registers with read side effects
+  fn cpu_peek(&mut self, addr: usize) -> Option<u8> {
+    self.cpu_read(addr)
+  }
   fn open_bus(&mut self, _addr: usize, _val: u8) {}
   fn debug_print_vec(&mut self) {}
 }
@@ -101,10 +131,21 @@ pub fn load_rom(cart: &Cartridge) -> Result<MapperType, Box<dyn Error>> {
     1 => Ok(MMC1::load(cart)),
     2 => Ok(Uxrom::load(cart)),
     71 => Ok(Uxrom::load(cart)), // TODO: Mapper 71 has slight differences from Uxrom
+    20 if cart.disk.is_some() => Ok(Fds::load(cart)),
+    31 if cart.nsf.is_some() => Ok(NsfPlayer::load(cart)),
     _ => Err(Box::new(ErrorMissingMapper::new(cart.header.mapper_num))),
   }
 }
 
+/// Copies the cartridge trainer (if any) to PRG-RAM $7000-$71FF, done once at power-up.
+pub fn load_trainer<M: MemWrite>(cart: &Cartridge, prg_ram: &mut M) {
+  if let Some(trainer) = &cart.trainer {
+    for (i, value) in trainer.iter().enumerate() {
+      prg_ram.write(TRAINER_ADDR + i, *value);
+    }
+  }
+}
+
 impl Mapper for NullMapper {}
 impl MemRead for NullMapper {}
 impl MemWrite for NullMapper {}
@@ -3,7 +3,7 @@ use std::fmt;
 use crate::Cartridge;
 use crate::nes::{
   memory::{MemRead, MemWrite, Memory},
-  mapper::{Mapper, MapperType, MirroringType},
+  mapper::{self, Mapper, MapperType, MirroringType},
 };
 
 //const PRG_RAM_SIZE
@@ -25,18 +25,19 @@ impl fmt::Display for Nrom {
 
 impl Nrom {
   pub fn load(cartridge: &Cartridge) -> MapperType {
-    let nrom = Self {
-      prg_ram: Memory::ram(8 * 1024),
+    let mut nrom = Self {
+      prg_ram: Memory::ram(cartridge.header.prg_ram_total()),
       prg_rom: Memory::rom_from_bytes(&cartridge.prg_rom),
       chr_rom: {match &cartridge.chr_rom {
         Some(chr_rom) => {
           println!();
           Memory::rom_from_bytes(&chr_rom)
         },
-        None => Memory::new()
+        None => Memory::ram(cartridge.header.chr_ram_total())
       }},
       mirroring: cartridge.header.mirroring_type,
     };
+    mapper::load_trainer(cartridge, &mut nrom.prg_ram);
     nrom.into()
   }
 }
@@ -54,6 +55,7 @@ impl Mapper for Nrom {
   fn irq_pending(&mut self) -> bool {
     false
   }
+  fn cpu_clock(&mut self) {}
   fn battery_backed(&self) -> bool {
     false
   }
@@ -64,6 +66,13 @@ impl Mapper for Nrom {
     0
   }
   fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      0x6000..=0x7FFF if !self.prg_ram.is_empty() => Some(self.prg_ram.read(addr)),
+      0x8000..=0xFFFF => Some(self.prg_rom.read(addr)),
+      _ => None,
+    }
+  }
   fn open_bus(&mut self, _addr: usize, _val: u8) {}
 }
 
@@ -71,9 +80,7 @@ impl MemRead for Nrom {
   fn read(&mut self, addr: usize) -> u8 {
     match addr {
       0x0000..=0x1FFF => self.chr_rom.read(addr),
-      0x6000..=0x7FFF => self.prg_ram.read(addr),
-      0x8000..=0xFFFF => self.prg_rom.read(addr),
-      _ => 0,
+      _ => self.cpu_read(addr).unwrap_or(0),
     }
   }
 }
@@ -82,6 +89,7 @@ impl MemWrite for Nrom {
   fn write(&mut self, addr: usize, value: u8) {
     match addr {
       0x0000..=0x1FFF => self.chr_rom.write(addr, value),
+      0x6000..=0x7FFF => self.prg_ram.write(addr, value),
       0x8000..=0xFFFF => self.prg_rom.write(addr, value),
       _ => (),
     }
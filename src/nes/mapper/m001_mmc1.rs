@@ -3,14 +3,12 @@ use std::fmt;
 use crate::Cartridge;
 use crate::nes::{
   memory::{MemRead, MemWrite, BankableMemory},
-  mapper::{Mapper, MapperType, MirroringType},
+  mapper::{self, Mapper, MapperType, MirroringType},
 };
 
 const PRG_RAM_WINDOW: usize = 8 * 1024;
-const PRG_RAM_SIZE: usize = 8 * 1024;
 const PRG_ROM_WINDOW: usize = 16 * 1024;
 const CHR_WINDOW: usize = 4 * 1024;
-const CHR_SIZE: usize = 8 * 1024;
 
 #[derive(Debug, Clone)]
 pub struct MMC1 {
@@ -34,20 +32,13 @@ impl fmt::Display for MMC1 {
 impl MMC1 {
   pub fn load(cartridge: &Cartridge) -> MapperType {
     let mut mmc1 = Self {
-      prg_ram: {
-        if cartridge.header.prg_ram_size == 0 {
-          BankableMemory::ram(PRG_RAM_SIZE, PRG_RAM_WINDOW)
-        }
-        else {
-          BankableMemory::ram(cartridge.header.prg_ram_size, PRG_RAM_WINDOW)
-        }
-      },
+      prg_ram: BankableMemory::ram(cartridge.header.prg_ram_total(), PRG_RAM_WINDOW),
       prg_rom: BankableMemory::rom_from_bytes(&cartridge.prg_rom, PRG_ROM_WINDOW),
       chr: {match &cartridge.chr_rom {
         Some(chr_rom) => {
           BankableMemory::ram_from_bytes(&chr_rom, CHR_WINDOW)
         },
-        None => BankableMemory::ram(CHR_SIZE, CHR_WINDOW)
+        None => BankableMemory::ram(cartridge.header.chr_ram_total(), CHR_WINDOW)
       }},
       mirroring: cartridge.header.mirroring_type,
       shift_reg: 0b0001_0000,
@@ -57,6 +48,7 @@ impl MMC1 {
       prg_bank: 0,
     };
     mmc1.prg_ram.add_bank_range(0x6000, 0x7FFF);
+    mapper::load_trainer(cartridge, &mut mmc1.prg_ram);
     mmc1.prg_rom.add_bank_range(0x8000, 0xFFFF);
     mmc1.prg_rom.set_bank(0xC000, mmc1.prg_rom.last_bank());
     mmc1.chr.add_bank_range(0x0000, 0x1FFF);
@@ -78,6 +70,7 @@ impl Mapper for MMC1 {
   fn irq_pending(&mut self) -> bool {
     false
   }
+  fn cpu_clock(&mut self) {}
   fn battery_backed(&self) -> bool {
     false
   }
@@ -88,6 +81,15 @@ impl Mapper for MMC1 {
     0
   }
   fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  /// PRG RAM reads are open bus while it is disabled
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      0x6000..=0x7FFF if self.prg_bank & 0b10000 == 0 && !self.prg_ram.is_empty() => Some(self.prg_ram.read(addr)),
+      0x8000..=0xBFFF => Some(self.prg_rom.read(addr)),
+      0xC000..=0xFFFF => Some(self.prg_rom.read(addr)),
+      _ => None,
+    }
+  }
   fn open_bus(&mut self, _addr: usize, _val: u8) {}
 }
 
@@ -95,17 +97,7 @@ impl MemRead for MMC1 {
   fn read(&mut self, addr: usize) -> u8 {
     match addr {
       0x0000..=0x1FFF => self.chr.read(addr),
-      0x6000..=0x7FFF => {
-        if self.prg_bank & 0b10000 == 0 {
-          self.prg_ram.read(addr)
-        }
-        else {
-          0
-        }
-      },
-      0x8000..=0xBFFF => self.prg_rom.read(addr),
-      0xC000..=0xFFFF => self.prg_rom.read(addr),
-      _ => 0,
+      _ => self.cpu_read(addr).unwrap_or(0),
     }
   }
 }
@@ -2,18 +2,18 @@ use std::fmt;
 
 use crate::Cartridge;
 use crate::nes::{
-  memory::{MemRead, MemWrite, BankableMemory},
-  mapper::{Mapper, MapperType, MirroringType},
+  memory::{MemRead, MemWrite, Memory, BankableMemory},
+  mapper::{self, Mapper, MapperType, MirroringType},
 };
 
 const PRG_ROM_WINDOW: usize = 16 * 1024;
 const CHR_WINDOW: usize = 8 * 1024;
-const CHR_SIZE: usize = 8 * 1024;
 
 //const PRG_RAM_SIZE
 
 #[derive(Debug, Clone)]
 pub struct Uxrom {
+  prg_ram: Memory,
   prg_rom: BankableMemory,
   chr: BankableMemory,
   mirroring: MirroringType,
@@ -28,18 +28,20 @@ impl fmt::Display for Uxrom {
 impl Uxrom {
   pub fn load(cartridge: &Cartridge) -> MapperType {
     let mut uxrom = Self {
+      prg_ram: Memory::ram(cartridge.header.prg_ram_total()),
       prg_rom: BankableMemory::rom_from_bytes(&cartridge.prg_rom, PRG_ROM_WINDOW),
       chr: {match &cartridge.chr_rom {
         Some(chr_rom) => {
           BankableMemory::ram_from_bytes(&chr_rom, CHR_WINDOW)
         },
-        None => BankableMemory::ram(CHR_SIZE, CHR_WINDOW)
+        None => BankableMemory::ram(cartridge.header.chr_ram_total(), CHR_WINDOW)
       }},
       mirroring: cartridge.header.mirroring_type,
     };
     uxrom.prg_rom.add_bank_range(0x8000, 0xFFFF);
     uxrom.prg_rom.set_bank(0xC000, uxrom.prg_rom.last_bank());
     uxrom.chr.add_bank_range(0x0000, 0x1FFF);
+    mapper::load_trainer(cartridge, &mut uxrom.prg_ram);
     uxrom.into()
   }
 }
@@ -57,6 +59,7 @@ impl Mapper for Uxrom {
   fn irq_pending(&mut self) -> bool {
     false
   }
+  fn cpu_clock(&mut self) {}
   fn battery_backed(&self) -> bool {
     false
   }
@@ -67,6 +70,14 @@ impl Mapper for Uxrom {
     0
   }
   fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      0x6000..=0x7FFF if !self.prg_ram.is_empty() => Some(self.prg_ram.read(addr)),
+      0x8000..=0xBFFF => Some(self.prg_rom.read(addr)),
+      0xC000..=0xFFFF => Some(self.prg_rom.read(addr)),
+      _ => None,
+    }
+  }
   fn open_bus(&mut self, _addr: usize, _val: u8) {}
 }
 
@@ -74,9 +85,7 @@ impl MemRead for Uxrom {
   fn read(&mut self, addr: usize) -> u8 {
     match addr {
       0x0000..=0x1FFF => self.chr.read(addr),
-      0x8000..=0xBFFF => self.prg_rom.read(addr),
-      0xC000..=0xFFFF => self.prg_rom.read(addr),
-      _ => 0,
+      _ => self.cpu_read(addr).unwrap_or(0),
     }
   }
 }
@@ -85,6 +94,7 @@ impl MemWrite for Uxrom {
   fn write(&mut self, addr: usize, value: u8) {
     match addr {
       0x0000..=0x1FFF => self.chr.write(addr, value),
+      0x6000..=0x7FFF => self.prg_ram.write(addr, value),
       0x8000..=0xFFFF => {
         let v = value & 0b0000_1111;
         self.prg_rom.set_bank(0x8000, v.into());
@@ -0,0 +1,469 @@
+pub mod audio;
+
+use std::fmt;
+
+use crate::Cartridge;
+use crate::nes::{
+  memory::{MemRead, MemWrite, Memory},
+  mapper::{Mapper, MapperType, MirroringType},
+  cartridge::disk::{self, DiskImage},
+};
+use audio::FdsAudio;
+
+const PRG_RAM_SIZE: usize = 32 * 1024;
+const CHR_RAM_SIZE: usize = 8 * 1024;
+
+/// Leading gap before the first block and gap between blocks, in bytes
+const GAP_START: usize = 28300 / 8;
+const GAP_BLOCK: usize = 976 / 8;
+/// Bytes the head goes over before reaching the end of the disk
+const RAW_SIDE_SIZE: usize = 76_000;
+const BLOCK_START_MARK: u8 = 0x80;
+
+/// CPU cycles per byte at 96.4 kbit/s
+const BYTE_DELAY: u32 = 150;
+const HEAD_REWIND_DELAY: u32 = 50_000;
+/// Long enough for the game to notice the drive is empty when switching sides
+const DISK_SWITCH_DELAY: u32 = 2_000_000;
+
+/// Famicom Disk System RAM adapter and disk drive.
+/// Sides are kept as the bytes the head sees: gaps, block start marks and CRCs included.
+#[derive(Debug, Clone)]
+pub struct Fds {
+  bios: Memory,
+  prg_ram: Memory,
+  chr_ram: Memory,
+  mirroring: MirroringType,
+  audio: FdsAudio,
+
+  disk: DiskImage,
+  sides: Vec<Vec<u8>>,
+  sides_modified: Vec<bool>,
+  side: usize,
+  inserted: bool,
+  insert_delay: u32,
+
+  timer_reload: u16,
+  timer_counter: u16,
+  timer_repeat: bool,
+  timer_enabled: bool,
+  timer_irq: bool,
+
+  disk_reg_enabled: bool,
+  sound_reg_enabled: bool,
+  motor_on: bool,
+  reset_transfer: bool,
+  read_mode: bool,
+  crc_control: bool,
+  prev_crc_control: bool,
+  disk_ready: bool,
+  disk_irq_enabled: bool,
+  disk_irq: bool,
+
+  transfer_complete: bool,
+  end_of_head: bool,
+  scanning: bool,
+  gap_ended: bool,
+  position: usize,
+  delay: u32,
+  crc: u16,
+  read_data: u8,
+  write_data: u8,
+  ext_output: u8,
+}
+
+impl fmt::Display for Fds {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "FDS: {} sides, side {} {}", self.sides.len(), self.side
+      , if self.inserted {"inserted"} else {"ejected"})
+  }
+}
+
+impl Fds {
+  pub fn load(cartridge: &Cartridge) -> MapperType {
+    let disk = cartridge.disk.as_ref().expect("FDS mapper without a disk image");
+    Self {
+      bios: Memory::rom_from_bytes(&cartridge.prg_rom),
+      prg_ram: Memory::ram(PRG_RAM_SIZE),
+      chr_ram: Memory::ram(CHR_RAM_SIZE),
+      mirroring: cartridge.header.mirroring_type,
+      audio: FdsAudio::new(),
+
+      disk: disk.clone(),
+      sides: disk.sides.iter().map(|side| Self::side_to_raw(side)).collect(),
+      sides_modified: vec![false; disk.sides.len()],
+      side: 0,
+      inserted: true,
+      insert_delay: 0,
+
+      timer_reload: 0,
+      timer_counter: 0,
+      timer_repeat: false,
+      timer_enabled: false,
+      timer_irq: false,
+
+      disk_reg_enabled: true,
+      sound_reg_enabled: true,
+      motor_on: false,
+      reset_transfer: false,
+      read_mode: true,
+      crc_control: false,
+      prev_crc_control: false,
+      disk_ready: false,
+      disk_irq_enabled: false,
+      disk_irq: false,
+
+      transfer_complete: false,
+      end_of_head: true,
+      scanning: false,
+      gap_ended: false,
+      position: 0,
+      delay: 0,
+      crc: 0,
+      read_data: 0,
+      write_data: 0,
+      ext_output: 0,
+    }.into()
+  }
+
+  fn side_to_raw(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; GAP_START];
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(len) = disk::block_len(&side[pos..], file_size) {
+      if pos + len > side.len() {
+        break;
+      }
+      let block = &side[pos..(pos + len)];
+      file_size = disk::file_header_size(block);
+      let start = raw.len();
+      raw.push(BLOCK_START_MARK);
+      raw.extend_from_slice(block);
+      let crc = disk::crc(&raw[start..]);
+      raw.extend_from_slice(&crc.to_le_bytes());
+      raw.resize(raw.len() + GAP_BLOCK, 0);
+      pos += len;
+    }
+    raw.resize(RAW_SIDE_SIZE.max(raw.len()), 0);
+    raw
+  }
+
+  fn side_from_raw(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(disk::SIDE_SIZE);
+    let mut pos = 0;
+    let mut file_size = 0;
+    while let Some(mark) = raw[pos..].iter().position(|v| *v != 0) {
+      pos += mark + 1;
+      if raw[pos - 1] != BLOCK_START_MARK {
+        break;
+      }
+      let len = match disk::block_len(&raw[pos..], file_size) {
+        Some(len) if pos + len <= raw.len() => len,
+        _ => break,
+      };
+      file_size = disk::file_header_size(&raw[pos..(pos + len)]);
+      side.extend_from_slice(&raw[pos..(pos + len)]);
+      pos = (pos + len + 2).min(raw.len());
+    }
+    side.resize(disk::SIDE_SIZE, 0);
+    side
+  }
+
+  /// Disk image in the layout it was loaded from, if the game wrote to it.
+  /// Sides never written are kept byte for byte.
+  pub fn modified_image(&self) -> Option<Vec<u8>> {
+    if !self.sides_modified.contains(&true) {
+      return None;
+    }
+    let mut image = self.disk.clone();
+    for (i, raw) in self.sides.iter().enumerate() {
+      if self.sides_modified[i] {
+        image.sides[i] = Self::side_from_raw(raw);
+      }
+    }
+    Some(image.to_bytes())
+  }
+
+  fn side_name(&self) -> String {
+    format!("disk {} side {}", self.side / 2 + 1, if self.side % 2 == 0 {'A'} else {'B'})
+  }
+
+  /// Ejects the disk, or inserts it back if the drive is empty.
+  pub fn eject_insert(&mut self) {
+    self.insert_delay = 0;
+    self.inserted = !self.inserted;
+    if self.inserted {
+      println!("FDS: {} inserted", self.side_name());
+    }
+    else {
+      println!("FDS: disk ejected");
+    }
+  }
+
+  /// Ejects the disk and inserts the next side once the game had time to see the empty drive.
+  pub fn switch_side(&mut self) {
+    self.side = (self.side + 1) % self.sides.len();
+    self.inserted = false;
+    self.insert_delay = DISK_SWITCH_DELAY;
+    println!("FDS: switching to {}", self.side_name());
+  }
+
+  fn timer_tick(&mut self) {
+    if self.timer_enabled {
+      if self.timer_counter == 0 {
+        self.timer_irq = true;
+        self.timer_counter = self.timer_reload;
+        if !self.timer_repeat {
+          self.timer_enabled = false;
+        }
+      }
+      else {
+        self.timer_counter -= 1;
+      }
+    }
+  }
+
+  fn update_crc(&mut self, value: u8) {
+    for bit in 0..8 {
+      let carry = self.crc & 1;
+      self.crc >>= 1;
+      if carry != 0 {
+        self.crc ^= 0x8408;
+      }
+      if value & (1 << bit) != 0 {
+        self.crc ^= 0x8000;
+      }
+    }
+  }
+
+  fn drive_tick(&mut self) {
+    if self.insert_delay > 0 {
+      self.insert_delay -= 1;
+      if self.insert_delay == 0 {
+        self.inserted = true;
+        println!("FDS: {} inserted", self.side_name());
+      }
+    }
+    if !self.inserted || !self.motor_on {
+      self.end_of_head = true;
+      self.scanning = false;
+      return;
+    }
+    if self.reset_transfer && !self.scanning {
+      return;
+    }
+    if self.end_of_head {
+      self.delay = HEAD_REWIND_DELAY;
+      self.end_of_head = false;
+      self.position = 0;
+      self.gap_ended = false;
+      return;
+    }
+    if self.delay > 0 {
+      self.delay -= 1;
+      return;
+    }
+
+    self.scanning = true;
+    let mut need_irq = self.disk_irq_enabled;
+    if self.read_mode {
+      let value = self.sides[self.side][self.position];
+      if !self.disk_ready {
+        self.gap_ended = false;
+      }
+      else if value != 0 && !self.gap_ended {
+        // the start mark itself is not transferred
+        self.gap_ended = true;
+        need_irq = false;
+      }
+      if self.gap_ended {
+        self.transfer_complete = true;
+        self.read_data = value;
+        if need_irq {
+          self.disk_irq = true;
+        }
+      }
+    }
+    else {
+      let mut value = 0;
+      if !self.crc_control {
+        self.transfer_complete = true;
+        value = self.write_data;
+        if need_irq {
+          self.disk_irq = true;
+        }
+      }
+      if !self.disk_ready {
+        value = 0;
+        self.crc = 0;
+      }
+      if !self.crc_control {
+        self.update_crc(value);
+      }
+      else {
+        if !self.prev_crc_control {
+          self.update_crc(0);
+          self.update_crc(0);
+        }
+        value = (self.crc & 0xFF) as u8;
+        self.crc >>= 8;
+      }
+      // the write head trails the read head by 2 bytes
+      if self.position >= 2 {
+        let raw = &mut self.sides[self.side][self.position - 2];
+        if *raw != value {
+          *raw = value;
+          self.sides_modified[self.side] = true;
+        }
+      }
+      self.gap_ended = false;
+    }
+    self.prev_crc_control = self.crc_control;
+
+    self.position += 1;
+    if self.position >= self.sides[self.side].len() {
+      self.motor_on = false;
+    }
+    else {
+      self.delay = BYTE_DELAY;
+    }
+  }
+
+  fn drive_status(&self) -> u8 {
+    let mut status = 0;
+    if !self.inserted {
+      status |= 0b0000_0101;
+    }
+    if !self.inserted || !self.scanning {
+      status |= 0b0000_0010;
+    }
+    status
+  }
+}
+
+impl Mapper for Fds {
+  fn mirroring(&self) -> MirroringType {
+    self.mirroring
+  }
+  fn irq_pending(&mut self) -> bool {
+    self.timer_irq || self.disk_irq
+  }
+  fn cpu_clock(&mut self) {
+    self.timer_tick();
+    self.audio.tick();
+    self.drive_tick();
+  }
+  fn expansion_audio(&self) -> f32 {
+    self.audio.output()
+  }
+  fn fds_audio(&self) -> bool {
+    true
+  }
+  fn battery_backed(&self) -> bool {
+    false
+  }
+  fn use_ciram(&self, _addr: usize) -> bool {
+    true
+  }
+  fn nametable_page(&self, _addr: usize) -> usize {
+    0
+  }
+  fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      0x4030 if self.disk_reg_enabled => {
+        let mut value = 0;
+        if self.timer_irq {
+          value |= 0b0000_0001;
+        }
+        if self.transfer_complete {
+          value |= 0b0000_0010;
+        }
+        if self.end_of_head {
+          value |= 0b0100_0000;
+        }
+        self.transfer_complete = false;
+        self.timer_irq = false;
+        self.disk_irq = false;
+        Some(value)
+      },
+      0x4031 if self.disk_reg_enabled => {
+        self.transfer_complete = false;
+        self.disk_irq = false;
+        Some(self.read_data)
+      },
+      0x4032 if self.disk_reg_enabled => Some(self.drive_status()),
+      // expansion port outputs read back, battery good
+      0x4033 if self.disk_reg_enabled => Some((self.ext_output & 0b0111_1111) | 0b1000_0000),
+      0x4040..=0x4097 if self.sound_reg_enabled => Some(self.audio.read(addr)),
+      0x6000..=0xDFFF => Some(self.prg_ram.read(addr - 0x6000)),
+      0xE000..=0xFFFF => Some(self.bios.read(addr)),
+      _ => None,
+    }
+  }
+  fn cpu_peek(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      // reading the status and data registers acknowledges the IRQs
+      0x4030 | 0x4031 => None,
+      _ => self.cpu_read(addr),
+    }
+  }
+  fn open_bus(&mut self, _addr: usize, _val: u8) {}
+}
+
+impl MemRead for Fds {
+  fn read(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x0000..=0x1FFF => self.chr_ram.read(addr),
+      _ => self.cpu_read(addr).unwrap_or(0),
+    }
+  }
+}
+
+impl MemWrite for Fds {
+  fn write(&mut self, addr: usize, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.chr_ram.write(addr, value),
+      0x4020 => self.timer_reload = (self.timer_reload & 0xFF00) | (value as u16),
+      0x4021 => self.timer_reload = (self.timer_reload & 0x00FF) | ((value as u16) << 8),
+      0x4022 => {
+        self.timer_repeat = value & 0b0000_0001 != 0;
+        self.timer_enabled = value & 0b0000_0010 != 0 && self.disk_reg_enabled;
+        if self.timer_enabled {
+          self.timer_counter = self.timer_reload;
+        }
+        else {
+          self.timer_irq = false;
+        }
+      },
+      0x4023 => {
+        self.disk_reg_enabled = value & 0b0000_0001 != 0;
+        self.sound_reg_enabled = value & 0b0000_0010 != 0;
+        if !self.disk_reg_enabled {
+          self.timer_enabled = false;
+          self.timer_irq = false;
+          self.disk_irq = false;
+        }
+      },
+      0x4024 if self.disk_reg_enabled => {
+        self.write_data = value;
+        self.transfer_complete = false;
+        self.disk_irq = false;
+      },
+      0x4025 if self.disk_reg_enabled => {
+        self.motor_on = value & 0b0000_0001 != 0;
+        self.reset_transfer = value & 0b0000_0010 != 0;
+        self.read_mode = value & 0b0000_0100 != 0;
+        self.mirroring = if value & 0b0000_1000 != 0 {MirroringType::Horizontal} else {MirroringType::Vertical};
+        self.crc_control = value & 0b0001_0000 != 0;
+        self.disk_ready = value & 0b0100_0000 != 0;
+        self.disk_irq_enabled = value & 0b1000_0000 != 0;
+        self.disk_irq = false;
+      },
+      0x4026 if self.disk_reg_enabled => self.ext_output = value,
+      0x4040..=0x408A if self.sound_reg_enabled => self.audio.write(addr, value),
+      0x6000..=0xDFFF => self.prg_ram.write(addr - 0x6000, value),
+      _ => (),
+    }
+  }
+}
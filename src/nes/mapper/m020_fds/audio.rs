@@ -0,0 +1,282 @@
+const WAVE_TABLE_SIZE: usize = 64;
+const MASTER_VOLUME_TABLE: [u32; 4] = [36, 24, 17, 14];
+/// Mod table entries, `None` resets the mod counter to 0
+const MOD_TABLE_STEP: [Option<i32>; 8] = [Some(0), Some(1), Some(2), Some(4), None, Some(-4), Some(-2), Some(-1)];
+/// Full volume wave is about 2.4 times a full volume pulse channel
+const OUTPUT_SCALE: f32 = 15.0 * 2.4 / 63.0;
+
+/// Volume or mod envelope, with the 12-bit frequency register that follows it.
+#[derive(Debug, Clone)]
+struct Envelope {
+  speed: u8,
+  gain: u8,
+  disabled: bool,
+  increase: bool,
+  frequency: u16,
+  timer: u32,
+  master_speed: u8,
+}
+
+impl Envelope {
+  fn new() -> Self {
+    Self {
+      speed: 0,
+      gain: 0,
+      disabled: false,
+      increase: false,
+      frequency: 0,
+      timer: 0,
+      master_speed: 0xE8,
+    }
+  }
+
+  fn write(&mut self, reg: usize, value: u8) {
+    match reg & 0b11 {
+      0 => {
+        self.speed = value & 0b0011_1111;
+        self.increase = value & 0b0100_0000 != 0;
+        self.disabled = value & 0b1000_0000 != 0;
+        self.reset_timer();
+        if self.disabled {
+          self.gain = self.speed;
+        }
+      },
+      2 => self.frequency = (self.frequency & 0x0F00) | (value as u16),
+      3 => self.frequency = (self.frequency & 0x00FF) | (((value & 0b0000_1111) as u16) << 8),
+      _ => (),
+    }
+  }
+
+  fn reset_timer(&mut self) {
+    self.timer = 8 * (self.speed as u32 + 1) * (self.master_speed as u32);
+  }
+
+  /// Returns true when the gain was clocked.
+  fn tick(&mut self) -> bool {
+    if !self.disabled && self.master_speed > 0 {
+      self.timer = self.timer.saturating_sub(1);
+      if self.timer == 0 {
+        self.reset_timer();
+        if self.increase && self.gain < 32 {
+          self.gain += 1;
+        }
+        else if !self.increase && self.gain > 0 {
+          self.gain -= 1;
+        }
+        return true;
+      }
+    }
+    false
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Modulator {
+  envelope: Envelope,
+  counter: i32,
+  disabled: bool,
+  table: [u8; WAVE_TABLE_SIZE],
+  table_pos: usize,
+  accumulator: u16,
+  output: i32,
+}
+
+impl Modulator {
+  fn new() -> Self {
+    Self {
+      envelope: Envelope::new(),
+      counter: 0,
+      disabled: false,
+      table: [0; WAVE_TABLE_SIZE],
+      table_pos: 0,
+      accumulator: 0,
+      output: 0,
+    }
+  }
+
+  fn write(&mut self, addr: usize, value: u8) {
+    match addr {
+      0x4084 | 0x4086 => self.envelope.write(addr, value),
+      0x4085 => self.set_counter((value & 0b0111_1111) as i32),
+      0x4087 => {
+        self.envelope.write(addr, value);
+        self.disabled = value & 0b1000_0000 != 0;
+        if self.disabled {
+          self.accumulator = 0;
+        }
+      },
+      0x4088 => {
+        // the table is only writable while the unit is halted, each write fills 2 entries
+        if self.disabled {
+          self.table[self.table_pos] = value & 0b0000_0111;
+          self.table[(self.table_pos + 1) % WAVE_TABLE_SIZE] = value & 0b0000_0111;
+          self.table_pos = (self.table_pos + 2) % WAVE_TABLE_SIZE;
+        }
+      },
+      _ => (),
+    }
+  }
+
+  /// 7-bit signed counter
+  fn set_counter(&mut self, value: i32) {
+    self.counter = if value >= 64 {value - 128} else if value < -64 {value + 128} else {value};
+  }
+
+  fn enabled(&self) -> bool {
+    !self.disabled && self.envelope.frequency > 0
+  }
+
+  /// Returns true when the mod counter changed.
+  fn tick(&mut self) -> bool {
+    if self.enabled() {
+      let (accumulator, overflow) = self.accumulator.overflowing_add(self.envelope.frequency);
+      self.accumulator = accumulator;
+      if overflow {
+        match MOD_TABLE_STEP[self.table[self.table_pos] as usize] {
+          Some(step) => self.set_counter(self.counter + step),
+          None => self.set_counter(0),
+        }
+        self.table_pos = (self.table_pos + 1) % WAVE_TABLE_SIZE;
+        return true;
+      }
+    }
+    false
+  }
+
+  /// Pitch offset from the NESdev wiki formula, including its odd rounding.
+  fn update_output(&mut self, pitch: u16) {
+    let mut temp = self.counter * (self.envelope.gain as i32);
+    let remainder = temp & 0x0F;
+    temp >>= 4;
+    if remainder > 0 && temp & 0x80 == 0 {
+      temp += if self.counter < 0 {-1} else {2};
+    }
+    if temp >= 192 {
+      temp -= 256;
+    }
+    else if temp < -64 {
+      temp += 256;
+    }
+    temp *= pitch as i32;
+    let remainder = temp & 0x3F;
+    temp >>= 6;
+    if remainder >= 32 {
+      temp += 1;
+    }
+    self.output = temp;
+  }
+
+  fn output(&self) -> i32 {
+    if self.enabled() {self.output} else {0}
+  }
+}
+
+/// FDS expansion sound: a 64 step wavetable channel with a frequency modulator.
+#[derive(Debug, Clone)]
+pub struct FdsAudio {
+  wave_table: [u8; WAVE_TABLE_SIZE],
+  wave_write: bool,
+  volume: Envelope,
+  modulator: Modulator,
+  envelopes_halted: bool,
+  wave_halted: bool,
+  master_volume: usize,
+  wave_accumulator: u16,
+  wave_pos: usize,
+  output: u8,
+}
+
+impl FdsAudio {
+  pub fn new() -> Self {
+    Self {
+      wave_table: [0; WAVE_TABLE_SIZE],
+      wave_write: false,
+      volume: Envelope::new(),
+      modulator: Modulator::new(),
+      envelopes_halted: false,
+      wave_halted: false,
+      master_volume: 0,
+      wave_accumulator: 0,
+      wave_pos: 0,
+      output: 0,
+    }
+  }
+
+  /// Called once per CPU cycle
+  pub fn tick(&mut self) {
+    let pitch = self.volume.frequency;
+    if !self.wave_halted && !self.envelopes_halted {
+      self.volume.tick();
+      if self.modulator.envelope.tick() {
+        self.modulator.update_output(pitch);
+      }
+    }
+    if self.modulator.tick() {
+      self.modulator.update_output(pitch);
+    }
+    if self.wave_halted {
+      self.wave_pos = 0;
+      self.update_output();
+    }
+    else {
+      self.update_output();
+      let freq = pitch as i32 + self.modulator.output();
+      if freq > 0 && !self.wave_write {
+        let (accumulator, overflow) = self.wave_accumulator.overflowing_add(freq as u16);
+        self.wave_accumulator = accumulator;
+        if overflow {
+          self.wave_pos = (self.wave_pos + 1) % WAVE_TABLE_SIZE;
+        }
+      }
+    }
+  }
+
+  fn update_output(&mut self) {
+    let level = (self.volume.gain.min(32) as u32) * MASTER_VOLUME_TABLE[self.master_volume];
+    self.output = ((self.wave_table[self.wave_pos] as u32 * level) / 1152) as u8;
+  }
+
+  /// Same unit as an APU channel output
+  pub fn output(&self) -> f32 {
+    self.output as f32 * OUTPUT_SCALE
+  }
+
+  pub fn read(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x4040..=0x407F => self.wave_table[addr & 0x3F],
+      0x4090 => self.volume.gain,
+      0x4092 => self.modulator.envelope.gain,
+      _ => 0,
+    }
+  }
+
+  pub fn write(&mut self, addr: usize, value: u8) {
+    match addr {
+      0x4040..=0x407F => {
+        if self.wave_write {
+          self.wave_table[addr & 0x3F] = value & 0b0011_1111;
+        }
+      },
+      0x4080 | 0x4082 => self.volume.write(addr, value),
+      0x4083 => {
+        self.envelopes_halted = value & 0b0100_0000 != 0;
+        self.wave_halted = value & 0b1000_0000 != 0;
+        if self.envelopes_halted {
+          self.volume.reset_timer();
+          self.modulator.envelope.reset_timer();
+        }
+        self.volume.write(addr, value);
+      },
+      0x4084..=0x4088 => self.modulator.write(addr, value),
+      0x4089 => {
+        self.master_volume = (value & 0b0000_0011) as usize;
+        self.wave_write = value & 0b1000_0000 != 0;
+      },
+      0x408A => {
+        self.volume.master_speed = value;
+        self.modulator.envelope.master_speed = value;
+      },
+      _ => (),
+    }
+  }
+}
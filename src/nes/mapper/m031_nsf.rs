@@ -0,0 +1,287 @@
+use std::fmt;
+
+use crate::Cartridge;
+use crate::nes::{
+  memory::{MemRead, MemWrite, Memory},
+  mapper::{Mapper, MapperType, MirroringType},
+  mapper::m020_fds::audio::FdsAudio,
+  cartridge::{TimingType, nsf::NsfInfo},
+};
+
+const BANK_SIZE: usize = 0x1000;
+const WRAM_SIZE: usize = 8 * 1024;
+const CPU_FREQ_NTSC: f64 = 1_789_773.0;
+const CPU_FREQ_PAL: f64 = 1_662_607.0;
+
+const DRIVER_ADDR: usize = 0x4100;
+const REG_SONG: usize = 0x4180;
+const REG_REGION: usize = 0x4181;
+const REG_INIT_LO: usize = 0x4182;
+const REG_INIT_HI: usize = 0x4183;
+const REG_PLAY_LO: usize = 0x4184;
+const REG_PLAY_HI: usize = 0x4185;
+/// Write: INIT returned, start the play timer. Read: acknowledge the play IRQ.
+const REG_PLAY_TIMER: usize = 0x4186;
+
+const NMI_VECTOR: u16 = 0x4151;
+const RESET_VECTOR: u16 = 0x4100;
+const IRQ_VECTOR: u16 = 0x414A;
+
+/// Player driver, entered on reset:
+/// clears RAM and the APU, calls INIT with the song in A and the region in X,
+/// then waits for the play timer IRQ which calls PLAY.
+const DRIVER: [u8; 82] = [
+  0x78,             // $4100 SEI
+  0xD8,             //       CLD
+  0xA2, 0xFF,       //       LDX #$FF
+  0x9A,             //       TXS
+  0xA9, 0x00,       //       LDA #$00
+  0xAA,             //       TAX
+  0x95, 0x00,       // $4108 STA $00,X
+  0x9D, 0x00, 0x01, //       STA $0100,X
+  0x9D, 0x00, 0x02, //       STA $0200,X
+  0x9D, 0x00, 0x03, //       STA $0300,X
+  0x9D, 0x00, 0x04, //       STA $0400,X
+  0x9D, 0x00, 0x05, //       STA $0500,X
+  0x9D, 0x00, 0x06, //       STA $0600,X
+  0x9D, 0x00, 0x07, //       STA $0700,X
+  0xE8,             //       INX
+  0xD0, 0xE6,       //       BNE $4108
+  0xA2, 0x13,       //       LDX #$13
+  0x9D, 0x00, 0x40, // $4124 STA $4000,X
+  0xCA,             //       DEX
+  0x10, 0xFA,       //       BPL $4124
+  0xA9, 0x0F,       //       LDA #$0F
+  0x8D, 0x15, 0x40, //       STA $4015
+  0xA9, 0x40,       //       LDA #$40
+  0x8D, 0x17, 0x40, //       STA $4017
+  0xAD, 0x80, 0x41, //       LDA REG_SONG
+  0xAE, 0x81, 0x41, //       LDX REG_REGION
+  0x20, 0x44, 0x41, //       JSR $4144
+  0x8D, 0x86, 0x41, //       STA REG_PLAY_TIMER
+  0x58,             //       CLI
+  0x4C, 0x41, 0x41, // $4141 JMP $4141
+  0x6C, 0x82, 0x41, // $4144 JMP (REG_INIT_LO)
+  0x6C, 0x84, 0x41, // $4147 JMP (REG_PLAY_LO)
+  0xAD, 0x86, 0x41, // $414A LDA REG_PLAY_TIMER (IRQ)
+  0x20, 0x47, 0x41, //       JSR $4147
+  0x40,             //       RTI
+  0x40,             // $4151 RTI (NMI)
+];
+
+/// NSF player, the bank switching is the one of mapper 31: 4KB banks
+/// at $8000-$FFFF selected by $5FF8-$5FFF, plus $6000-$7FFF by $5FF6-$5FF7 on FDS tunes.
+#[derive(Debug, Clone)]
+pub struct NsfPlayer {
+  nsf: NsfInfo,
+  rom: Vec<u8>,
+  /// Banks for $6000-$FFFF
+  banks: [usize; 10],
+  wram: Memory,
+  fds_audio: Option<FdsAudio>,
+  chr_ram: Memory,
+
+  song: u8,
+  play_period: u32,
+  play_timer: u32,
+  playing: bool,
+  irq: bool,
+}
+
+impl fmt::Display for NsfPlayer {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "NSF: {}, {}", self.nsf.title, self.nsf.track_name(self.song))
+  }
+}
+
+impl NsfPlayer {
+  pub fn load(cartridge: &Cartridge) -> MapperType {
+    let nsf = cartridge.nsf.clone().expect("NSF player without a NSF file");
+    for chip in nsf.unsupported_chips() {
+      println!("NSF: {} expansion audio is not supported", chip);
+    }
+    let cpu_freq = if nsf.timing_type == TimingType::PAL_NES {CPU_FREQ_PAL} else {CPU_FREQ_NTSC};
+    let mut player = Self {
+      play_period: ((nsf.play_speed() as f64) * cpu_freq / 1_000_000.0) as u32,
+      song: nsf.starting_song,
+      nsf,
+      rom: Vec::new(),
+      banks: [0; 10],
+      wram: Memory::ram(WRAM_SIZE),
+      fds_audio: None,
+      chr_ram: Memory::ram(8 * 1024),
+
+      play_timer: 0,
+      playing: false,
+      irq: false,
+    };
+    player.select_song(player.song);
+    player.into()
+  }
+
+  pub fn song(&self) -> u8 {
+    self.song
+  }
+
+  pub fn total_songs(&self) -> u8 {
+    self.nsf.total_songs
+  }
+
+  /// Reloads the tune for `song` (0-based), the CPU must be reset afterward to run INIT.
+  pub fn select_song(&mut self, song: u8) {
+    self.song = song % self.nsf.total_songs;
+    self.load_rom();
+    self.wram = Memory::ram(WRAM_SIZE);
+    self.fds_audio = if self.nsf.fds() {Some(FdsAudio::new())} else {None};
+    self.playing = false;
+    self.irq = false;
+    println!("NSF: playing {}", self.nsf.track_name(self.song));
+  }
+
+  fn load_rom(&mut self) {
+    if self.nsf.banked() {
+      let padding = (self.nsf.load_addr as usize) & (BANK_SIZE - 1);
+      self.rom = vec![0; padding];
+      self.rom.extend_from_slice(&self.nsf.data);
+      let len = (self.rom.len() + BANK_SIZE - 1) / BANK_SIZE * BANK_SIZE;
+      self.rom.resize(len, 0);
+      for (i, bank) in self.nsf.bankswitch.iter().enumerate() {
+        self.banks[i + 2] = *bank as usize;
+      }
+      self.banks[0] = self.nsf.bankswitch[6] as usize;
+      self.banks[1] = self.nsf.bankswitch[7] as usize;
+    }
+    else {
+      // FDS tunes can load in the RAM at $6000-$7FFF
+      let base = if self.nsf.fds() {0x6000} else {0x8000};
+      self.rom = vec![0; 0x10000 - base];
+      let start = (self.nsf.load_addr as usize - base).min(self.rom.len());
+      let len = self.nsf.data.len().min(self.rom.len() - start);
+      self.rom[start..(start + len)].copy_from_slice(&self.nsf.data[..len]);
+      let first_slot = (base - 0x6000) / BANK_SIZE;
+      for (slot, bank) in self.banks.iter_mut().enumerate() {
+        *bank = slot.saturating_sub(first_slot);
+      }
+    }
+  }
+
+  /// $6000-$7FFF is plain RAM except on FDS tunes where the whole $6000-$FFFF is banked RAM.
+  fn rom_addr(&self, addr: usize) -> Option<usize> {
+    let slot = (addr - 0x6000) / BANK_SIZE;
+    if slot < 2 && !self.nsf.fds() {
+      return None;
+    }
+    Some((self.banks[slot] * BANK_SIZE + (addr & (BANK_SIZE - 1))) % self.rom.len())
+  }
+
+  fn vector(&self, addr: usize) -> u8 {
+    let vector = match addr & !1 {
+      0xFFFA => NMI_VECTOR,
+      0xFFFC => RESET_VECTOR,
+      _ => IRQ_VECTOR,
+    };
+    if addr & 1 == 0 {vector as u8} else {(vector >> 8) as u8}
+  }
+}
+
+impl Mapper for NsfPlayer {
+  fn mirroring(&self) -> MirroringType {
+    MirroringType::Horizontal
+  }
+  fn irq_pending(&mut self) -> bool {
+    self.irq
+  }
+  fn cpu_clock(&mut self) {
+    if self.playing {
+      if self.play_timer == 0 {
+        self.irq = true;
+        self.play_timer = self.play_period;
+      }
+      else {
+        self.play_timer -= 1;
+      }
+    }
+    if let Some(fds_audio) = &mut self.fds_audio {
+      fds_audio.tick();
+    }
+  }
+  fn expansion_audio(&self) -> f32 {
+    self.fds_audio.as_ref().map_or(0.0, |fds_audio| fds_audio.output())
+  }
+  fn fds_audio(&self) -> bool {
+    self.fds_audio.is_some()
+  }
+  fn battery_backed(&self) -> bool {
+    false
+  }
+  fn use_ciram(&self, _addr: usize) -> bool {
+    true
+  }
+  fn nametable_page(&self, _addr: usize) -> usize {
+    0
+  }
+  fn ppu_write(&mut self, _addr: usize, _val: u8) {}
+  fn cpu_read(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      0x4040..=0x4097 => self.fds_audio.as_mut().map(|fds_audio| fds_audio.read(addr)),
+      REG_SONG => Some(self.song),
+      REG_REGION => Some(if self.nsf.timing_type == TimingType::PAL_NES {1} else {0}),
+      REG_INIT_LO => Some(self.nsf.init_addr as u8),
+      REG_INIT_HI => Some((self.nsf.init_addr >> 8) as u8),
+      REG_PLAY_LO => Some(self.nsf.play_addr as u8),
+      REG_PLAY_HI => Some((self.nsf.play_addr >> 8) as u8),
+      REG_PLAY_TIMER => {
+        self.irq = false;
+        Some(0)
+      },
+      DRIVER_ADDR..=0x41FF => DRIVER.get(addr - DRIVER_ADDR).copied(),
+      0xFFFA..=0xFFFF => Some(self.vector(addr)),
+      0x6000..=0xFFFF => match self.rom_addr(addr) {
+        Some(rom_addr) => Some(self.rom[rom_addr]),
+        None => Some(self.wram.read(addr - 0x6000)),
+      },
+      _ => None,
+    }
+  }
+  fn cpu_peek(&mut self, addr: usize) -> Option<u8> {
+    match addr {
+      REG_PLAY_TIMER => None,
+      _ => self.cpu_read(addr),
+    }
+  }
+  fn open_bus(&mut self, _addr: usize, _val: u8) {}
+}
+
+impl MemRead for NsfPlayer {
+  fn read(&mut self, addr: usize) -> u8 {
+    match addr {
+      0x0000..=0x1FFF => self.chr_ram.read(addr),
+      _ => self.cpu_read(addr).unwrap_or(0),
+    }
+  }
+}
+
+impl MemWrite for NsfPlayer {
+  fn write(&mut self, addr: usize, value: u8) {
+    match addr {
+      0x0000..=0x1FFF => self.chr_ram.write(addr, value),
+      0x4040..=0x408A => {
+        if let Some(fds_audio) = &mut self.fds_audio {
+          fds_audio.write(addr, value);
+        }
+      },
+      REG_PLAY_TIMER => {
+        self.playing = true;
+        self.play_timer = self.play_period;
+      },
+      0x5FF6..=0x5FF7 if self.nsf.fds() && self.nsf.banked() => self.banks[addr - 0x5FF6] = value as usize,
+      0x5FF8..=0x5FFF if self.nsf.banked() => self.banks[addr - 0x5FF8 + 2] = value as usize,
+      0x6000..=0xFFFF => match self.rom_addr(addr) {
+        Some(rom_addr) if self.nsf.fds() => self.rom[rom_addr] = value,
+        Some(_) => (),
+        None => self.wram.write(addr - 0x6000, value),
+      },
+      _ => (),
+    }
+  }
+}
@@ -131,7 +131,7 @@ impl fmt::Display for BankableMemory {
 
 impl BankableMemory {
   pub fn with_capacity(capacity: usize, window: usize) -> Self {
-    let capacity = capacity + (capacity % window);
+    let capacity = if capacity % window == 0 {capacity} else {(capacity / window + 1) * window};
     let data = vec![0; capacity];
     Self {
       data,
@@ -150,7 +150,7 @@ impl BankableMemory {
 
   pub fn rom_from_bytes(bytes: &[u8], window: usize) -> Self {
     let mut rom = Self::rom(bytes.len(), window);
-    rom.data = bytes.to_vec();
+    rom.data[..bytes.len()].copy_from_slice(bytes);
     rom
   }
 
@@ -160,7 +160,7 @@ impl BankableMemory {
 
   pub fn ram_from_bytes(bytes: &[u8], window: usize) -> Self {
     let mut ram = Self::ram(bytes.len(), window);
-    ram.data = bytes.to_vec();
+    ram.data[..bytes.len()].copy_from_slice(bytes);
     ram
   }
 
@@ -1,13 +1,16 @@
 mod palette;
 mod register;
+mod font;
 pub mod memory;
 
 use crate::nes::{
   memory::{Memory},
   bus::Bus,
   clock::Clock,
+  region::Region,
 };
-use palette::{Palette, NesColor};
+use palette::Palette;
+pub use palette::NesColor;
 use register::*;
 use memory::*;
 
@@ -19,7 +22,10 @@ const FRAME_WIDTH: usize = 256;
 const PATTERN_TABLE_ADDR: u16 = 0x0000;
 const NAMETABLE_ADDR: u16 = 0x2000;
 const STARTING_SCANLINE: u32 = 0;
+/// Level of the components left out by the color emphasis
+const EMPHASIS_ATTENUATION: f32 = 0.816;
 
+#[derive(Clone)]
 pub struct Frame {
   pub width: usize,
   pub height: usize,
@@ -49,6 +55,40 @@ impl Frame {
   pub fn clear(&mut self) {
     self.pixels = vec![0; self.width * self.height * 4];
   }
+
+  pub fn fill(&mut self, color: NesColor) {
+    for y in 0..self.height {
+      for x in 0..self.width {
+        self.put_pixel(x, y, color);
+      }
+    }
+  }
+
+  /// Fills a rectangle, clipped to the frame
+  pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: NesColor) {
+    for py in y..(y + height).min(self.height) {
+      for px in x..(x + width).min(self.width) {
+        self.put_pixel(px, py, color);
+      }
+    }
+  }
+
+  /// Draws `text` with the built-in 3x5 font, clipped to the frame.
+  /// Returns the height of a line of text.
+  pub fn draw_text(&mut self, x: usize, y: usize, text: &str, color: NesColor) -> usize {
+    for (i, c) in text.chars().enumerate() {
+      let glyph_x = x + i * (font::GLYPH_WIDTH + 1);
+      for (row, bits) in font::glyph(c).iter().enumerate() {
+        for col in 0..font::GLYPH_WIDTH {
+          let (px, py) = (glyph_x + col, y + row);
+          if bits & (0b100 >> col) != 0 && px < self.width && py < self.height {
+            self.put_pixel(px, py, color);
+          }
+        }
+      }
+    }
+    font::GLYPH_HEIGHT + 2
+  }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -63,6 +103,7 @@ pub enum PPUEvent {
 }
 
 pub struct PPU {
+  region: Region,
   palette: Palette,
   frame: Frame,
   palette_mem: Memory,
@@ -99,10 +140,12 @@ impl Clock<bool> for PPU {
 }
 
 impl PPU {
-  pub fn new() -> Self {
+  pub fn new(region: Region) -> Self {
+    let frame_height = if region == Region::Ntsc {FRAME_HEIGHT_NTSC} else {FRAME_HEIGHT_PAL};
     Self {
+      region,
       palette: Palette::new(),
-      frame: Frame::new(FRAME_WIDTH, FRAME_HEIGHT_NTSC),
+      frame: Frame::new(FRAME_WIDTH, frame_height),
       palette_mem: Memory::ram(32),
       scanline_n : STARTING_SCANLINE,
       cycle_n: 0,
@@ -139,13 +182,13 @@ impl PPU {
   //+--------------- 0: Pattern table is at $0000-$1FFF
 
   fn scanline_vblank(&mut self, bus: &mut Bus) {
-    if self.cycle_n == 1  && self.scanline_n == 241 {
+    if self.cycle_n == 1  && self.scanline_n == self.region.vblank_scanline() {
       bus.ppu_mem.set_interupt(true);
       //println!("VBLANK (241, 1): {:#010b} {}", bus.ppu_mem.read_ctrl(), bus.ppu_mem.read_ctrl() & 0b1000_0000 != 0);
     }
   }
 
-  fn scanline_261(&mut self, bus: &mut Bus) {
+  fn scanline_prerender(&mut self, bus: &mut Bus) {
     if self.cycle_n == 1 {
       self.frame_finish = true;
       self.frame_n += 1;
@@ -268,7 +311,7 @@ impl PPU {
       (sp_color, priority, sprite0) = self.sprite_color(bus);
     }
 
-    let mut index: usize = (bus.ppu_read((bg_color + 0x3F00) as usize) % 64) as usize;
+    let mut index: usize = (bus.ppu_mem.palette_read(bg_color) % 64) as usize;
     index = if bus.ppu_mem.read_ctrl() & 1 == 1 {index & 0x30} else {index};
     let mut color = self.palette.color[index];
 
@@ -276,13 +319,38 @@ impl PPU {
       bus.ppu_mem.set_sprite_0hit(true);
     }
     if (priority && sp_color != 0x00) || !opaque_bg {
-      let mut index: usize = (bus.ppu_read((sp_color + 0x3F00) as usize) % 64) as usize;
+      let mut index: usize = (bus.ppu_mem.palette_read(sp_color) % 64) as usize;
       index = if bus.ppu_mem.read_ctrl() & 1 == 1 {index & 0x30} else {index};
       color = self.palette.color[index];
     }
+    let color = self.emphasis(color, bus.ppu_mem.read_mask());
     self.frame.put_pixel((self.cycle_n - 1) as usize, self.scanline_n as usize, color);
   }
 
+  fn prerender_scanline(&self) -> u32 {
+    self.region.scanlines() - 1
+  }
+
+  /// PPUMASK bits 5-7 darken the other color components, red and green are swapped on PAL and Dendy.
+  fn emphasis(&self, color: NesColor, mask: u8) -> NesColor {
+    let (mut red, mut green) = (mask & 0b0010_0000 != 0, mask & 0b0100_0000 != 0);
+    if self.region.swap_emphasis() {
+      std::mem::swap(&mut red, &mut green);
+    }
+    let blue = mask & 0b1000_0000 != 0;
+    if !(red || green || blue) {
+      return color;
+    }
+    let attenuate = |component: u8, emphasized: bool| {
+      if emphasized {component} else {((component as f32) * EMPHASIS_ATTENUATION) as u8}
+    };
+    NesColor {
+      R: attenuate(color.R, red),
+      G: attenuate(color.G, green),
+      B: attenuate(color.B, blue),
+    }
+  }
+
   fn handle_scanline(&mut self, bus: &mut Bus) -> bool{
     match self.scanline_n {
       0..=239 => {
@@ -300,10 +368,10 @@ impl PPU {
           self.oam_handle(bus);
         }
       },
-      261 => {self.scanline_261(bus);},
+      n if n == self.prerender_scanline() => {self.scanline_prerender(bus);},
       _ => {self.scanline_vblank(bus);}
     }
-    if ((self.scanline_n >= 0 && self.scanline_n <= 239) || self.scanline_n == 261) &&
+    if ((self.scanline_n >= 0 && self.scanline_n <= 239) || self.scanline_n == self.prerender_scanline()) &&
       ((self.cycle_n > 0 && self.cycle_n <= 256) || (self.cycle_n > 320 && self.cycle_n <= 336)) {
       self.reg.shift_back_reg();
     }
@@ -314,7 +382,7 @@ impl PPU {
       self.cycle_n = 0;
       self.work_cycle = 0;
       self.scanline_n = self.scanline_n.wrapping_add(1);
-      if self.scanline_n > 261 {
+      if self.scanline_n > self.prerender_scanline() {
         self.scanline_n = 0;
       }
       true
@@ -532,7 +600,7 @@ impl PPU {
     for y in 0..32 {
       for x in 0..32 {
         let addr: usize = nametable + x + y * 32;
-        let mut palette = (bus.ppu_read(addr) % 64) as usize;
+        let mut palette = (bus.ppu_peek(addr) % 64) as usize;
         if palette == 36 {
           palette = 63;
         }
@@ -548,7 +616,7 @@ impl PPU {
       if addr % 32 == 0 {
         println!("");
       }
-      let value = bus.ppu_read(addr);
+      let value = bus.ppu_peek(addr);
       print!("{:#04x}, ", value);
     }
   }
@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::nes::{
   memory::{Memory, MemRead, MemWrite},
-  mapper::{MirroringType, MapperType},
+  mapper::{Mapper, MirroringType, MapperType},
 };
 
 const NAMETABLE_ADDR: u16 = 0x2000;
@@ -59,6 +59,7 @@ pub struct PPUMemory {
   nmi_output: bool,
   mirroring_type: MirroringType,
   data_read_buffer: u8,
+  addr_bus: usize,
 }
 
 impl PPUMemory {
@@ -81,6 +82,7 @@ impl PPUMemory {
       nmi_output: true,
       mirroring_type: MirroringType::Horizontal,
       data_read_buffer: 0,
+      addr_bus: 0,
     }
   }
 }
@@ -139,6 +141,14 @@ impl PPUMemory {
     self.oam_addr = self.oam_addr.wrapping_add(1);
   }
 
+  pub fn set_addr_bus(&mut self, mapper: &mut MapperType, addr: usize) {
+    let addr = addr & 0x3FFF;
+    if addr != self.addr_bus {
+      self.addr_bus = addr;
+      mapper.ppu_bus_change(addr);
+    }
+  }
+
   pub fn set_mirroring(&mut self, mirroring_type: MirroringType) {
     self.mirroring_type = mirroring_type;
   }
@@ -196,6 +206,7 @@ impl PPUMemory {
         else {
           self.v += 1;
         }
+        self.set_addr_bus(mapper, self.v.into());
         value
       },
       _ => 0,
@@ -236,6 +247,7 @@ impl PPUMemory {
         else {
           self.t = (self.t & 0b0011_1111_0000_0000) | ((value & 0b1111_1111) as u16);
           self.v = self.t;
+          self.set_addr_bus(mapper, self.v.into());
         }
         self.w = !self.w;
       },
@@ -247,6 +259,7 @@ impl PPUMemory {
         else {
           self.v += 1;
         }
+        self.set_addr_bus(mapper, self.v.into());
       }
       _ => (),
     }
@@ -261,20 +274,34 @@ impl PPUMemory {
   }
 
   pub fn ppu_read(&mut self, mapper: &mut MapperType, addr: usize) -> u8 {
+    self.set_addr_bus(mapper, addr);
+    self.ppu_peek(mapper, addr)
+  }
+
+  /// Reads without driving the address bus, for the debug views
+  pub fn ppu_peek(&mut self, mapper: &mut MapperType, addr: usize) -> u8 {
     match addr {
       0x0000..=0x1FFF => mapper.read(addr),
       0x2000..=0x2FFF => self.vram.read(self.mirroring(addr)),
       0x3000..=0x3EFF => self.vram.read(self.mirroring(addr)),
-      0x3F10 => self.palette.read(0x00),
-      0x3F14 => self.palette.read(0x04),
-      0x3F18 => self.palette.read(0x08),
-      0x3F1C => self.palette.read(0x0C),
-      0x3F00..=0x3FFF => self.palette.read(addr & 0xFF),
+      0x3F00..=0x3FFF => self.palette_read(addr),
       _ => 0,
     }
- }
+  }
+
+  /// Palette RAM is inside the PPU, its reads never reach the external bus
+  pub fn palette_read(&mut self, addr: usize) -> u8 {
+    match addr & 0x1F {
+      0x10 => self.palette.read(0x00),
+      0x14 => self.palette.read(0x04),
+      0x18 => self.palette.read(0x08),
+      0x1C => self.palette.read(0x0C),
+      index => self.palette.read(index),
+    }
+  }
 
  pub fn ppu_write(&mut self, mapper: &mut MapperType, addr: usize, value: u8) {
+    self.set_addr_bus(mapper, addr);
     match addr {
       0x0000..=0x1FFF => mapper.write(addr, value),
       0x2000..=0x2FFF => self.vram.write(self.mirroring(addr), value),
@@ -0,0 +1,93 @@
+use std::fmt;
+
+use crate::nes::cartridge::TimingType;
+
+/// Console region, sets the clock ratios, the frame timings and the APU tables.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+  Ntsc,
+  Pal,
+  Dendy,
+}
+
+impl fmt::Display for Region {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let name = match self {
+      Region::Ntsc => "NTSC",
+      Region::Pal => "PAL",
+      Region::Dendy => "Dendy",
+    };
+    write!(f, "{}", name)
+  }
+}
+
+impl Region {
+  /// Multi-region games run as NTSC
+  pub fn from_timing(timing: TimingType) -> Self {
+    match timing {
+      TimingType::PAL_NES => Region::Pal,
+      TimingType::Dendy => Region::Dendy,
+      TimingType::NTSC_NES | TimingType::MUL_REG => Region::Ntsc,
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "ntsc" => Some(Region::Ntsc),
+      "pal" => Some(Region::Pal),
+      "dendy" => Some(Region::Dendy),
+      _ => None,
+    }
+  }
+
+  /// CPU, PPU and APU dividers of the master clock (21.477MHz NTSC, 26.602MHz PAL and Dendy):
+  /// 3 PPU dots per CPU cycle, 3.2 on PAL
+  pub fn clock_dividers(&self) -> (u32, u32, u32) {
+    match self {
+      Region::Ntsc => (12, 4, 24),
+      Region::Pal => (16, 5, 32),
+      Region::Dendy => (15, 5, 30),
+    }
+  }
+
+  pub fn cpu_freq(&self) -> f64 {
+    match self {
+      Region::Ntsc => 1_789_773.0,
+      Region::Pal => 1_662_607.0,
+      Region::Dendy => 1_773_448.0,
+    }
+  }
+
+  pub fn frame_rate(&self) -> f64 {
+    match self {
+      Region::Ntsc => 60.0988,
+      Region::Pal | Region::Dendy => 50.0070,
+    }
+  }
+
+  /// Scanlines per frame, the last one is the pre-render scanline
+  pub fn scanlines(&self) -> u32 {
+    match self {
+      Region::Ntsc => 262,
+      Region::Pal | Region::Dendy => 312,
+    }
+  }
+
+  /// The Dendy keeps NTSC's 20 vblank scanlines and puts the extra ones before vblank
+  pub fn vblank_scanline(&self) -> u32 {
+    match self {
+      Region::Ntsc | Region::Pal => 241,
+      Region::Dendy => 291,
+    }
+  }
+
+  /// PAL noise and DMC periods and frame sequencer, the Dendy uses NTSC's
+  pub fn pal_apu(&self) -> bool {
+    *self == Region::Pal
+  }
+
+  /// PAL and Dendy PPUs swap the red and green emphasis bits of PPUMASK
+  pub fn swap_emphasis(&self) -> bool {
+    *self != Region::Ntsc
+  }
+}
@@ -1,5 +1,13 @@
+mod unif;
+mod patch;
+
 use std::fs;
 use std::error::Error;
+use std::path::Path;
+
+use crate::nes::cartridge::{Cartridge, RomError, HEADER_SIZE, disk, nsf};
+
+const FDS_BIOS_FILENAME: &str = "disksys.rom";
 
 pub fn header_info(header : &[u8]) {
   for v in header {
@@ -20,12 +28,54 @@ pub fn header_info(header : &[u8]) {
   println!("{:#010b}", header[15]);
 }
 
-pub fn nes_rom_load(filename : &str) -> Result<Vec<u8>, Box<dyn Error>> {
-  let file = fs::read(filename)?;
-  if file[0] == 'N' as u8 && file[1] == 'E' as u8
-      && file[2] == 'S' as u8 && file[3] == 0x1A as u8 {
-    //println!("total len: {}", file.len());
-    header_info(&file[..16])
+/// Loads a rom, soft-patching it in memory with `patch_filename` or,
+/// if none is given, with a `<rom>.ips/.ups/.bps` found next to it.
+pub fn nes_rom_load(filename : &str, patch_filename: Option<&str>) -> Result<Vec<u8>, RomError> {
+  let mut file = fs::read(filename)?;
+  let patch_filename = match patch_filename {
+    Some(patch_filename) => Some(patch_filename.to_string()),
+    None => patch::find_patch(filename),
+  };
+  if let Some(patch_filename) = patch_filename {
+    file = patch::apply_file(&file, &patch_filename)?;
+    println!("patch applied: {}", patch_filename);
+  }
+  if file.starts_with(unif::MAGIC) {
+    file = unif::to_ines(&file)?;
+  }
+  if disk::is_disk_image(&file) || nsf::is_nsf(&file) {
+    return Ok(file);
   }
+  Cartridge::check_header(&file)?;
+  //println!("total len: {}", file.len());
+  header_info(&file[..HEADER_SIZE]);
   Ok(file)
 }
+
+/// Loads the FDS BIOS from `bios_filename` or, if none is given,
+/// from a disksys.rom next to the disk image or in the working directory.
+pub fn fds_bios_load(disk_filename: &str, bios_filename: Option<&str>) -> Result<Vec<u8>, RomError> {
+  let candidates = match bios_filename {
+    Some(bios_filename) => vec![Path::new(bios_filename).to_path_buf()],
+    None => vec![
+      Path::new(disk_filename).with_file_name(FDS_BIOS_FILENAME),
+      Path::new(FDS_BIOS_FILENAME).to_path_buf(),
+    ],
+  };
+  for candidate in &candidates {
+    if candidate.is_file() {
+      println!("FDS BIOS: {}", candidate.display());
+      return Ok(fs::read(candidate)?);
+    }
+  }
+  Err(RomError::MissingBios(format!("{} not found, use --bios <file>", FDS_BIOS_FILENAME)))
+}
+
+/// Saves the disk writes as `<disk>.ips`, a diff against the untouched image,
+/// so the original file is never modified and the save is applied at next load.
+pub fn save_disk_diff(disk_filename: &str, image: &[u8]) -> Result<String, Box<dyn Error>> {
+  let original = fs::read(disk_filename)?;
+  let patch_path = Path::new(disk_filename).with_extension("ips");
+  fs::write(&patch_path, patch::create_ips(&original, image))?;
+  Ok(patch_path.display().to_string())
+}
@@ -0,0 +1,497 @@
+use std::fmt;
+use std::fs;
+use std::error::Error;
+use std::path::Path;
+
+use crate::nes::cartridge::{hash, RomError};
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+/// A record at this offset would read as the end marker
+const IPS_EOF_OFFSET: usize = 0x454F46;
+const IPS_MAX_RECORD: usize = 0xFFFF;
+const UPS_MAGIC: &[u8] = b"UPS1";
+const BPS_MAGIC: &[u8] = b"BPS1";
+const FOOTER_SIZE: usize = 12;
+const INES_HEADER_SIZE: usize = 16;
+/// Largest patched rom accepted, sizes come from the patch and can't be trusted
+const MAX_TARGET_SIZE: usize = 16 * 1024 * 1024;
+
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "ups", "bps"];
+
+#[derive(Debug)]
+pub struct ErrorPatch {
+  msg: String,
+}
+
+impl ErrorPatch {
+  pub fn new(msg: &str) -> Self {
+    Self {
+      msg: msg.to_string(),
+    }
+  }
+}
+
+impl fmt::Display for ErrorPatch {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.msg)
+  }
+}
+
+impl Error for ErrorPatch {}
+
+fn patch_error<T>(msg: &str) -> Result<T, Box<dyn Error>> {
+  Err(Box::new(ErrorPatch::new(msg)))
+}
+
+/// Looks for `<rom>.ips/.ups/.bps` next to the rom, then `<rom>.<ext>.ips/...`.
+pub fn find_patch(rom_filename: &str) -> Option<String> {
+  let rom_path = Path::new(rom_filename);
+  for ext in PATCH_EXTENSIONS {
+    let candidates = [
+      rom_path.with_extension(ext),
+      Path::new(&format!("{}.{}", rom_filename, ext)).to_path_buf(),
+    ];
+    for candidate in candidates {
+      if candidate.is_file() {
+        return candidate.to_str().map(|s| s.to_string());
+      }
+    }
+  }
+  None
+}
+
+/// Applies the patch file to the rom in memory, the files on disk are never written.
+pub fn apply_file(rom: &[u8], patch_filename: &str) -> Result<Vec<u8>, RomError> {
+  let patch_error = |msg: String| RomError::Patch{filename: patch_filename.to_string(), msg};
+  let patch = fs::read(patch_filename).map_err(|e| patch_error(e.to_string()))?;
+  apply(rom, &patch).map_err(|e| patch_error(e.to_string()))
+}
+
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  if patch.starts_with(IPS_MAGIC) {
+    apply_ips(rom, patch)
+  }
+  else if patch.starts_with(UPS_MAGIC) || patch.starts_with(BPS_MAGIC) {
+    let apply_fn = if patch.starts_with(UPS_MAGIC) {apply_ups} else {apply_bps};
+    match apply_fn(rom, patch) {
+      Ok(target) => Ok(target),
+      // UPS/BPS patches are often made against headerless dumps
+      Err(e) if rom.len() > INES_HEADER_SIZE && rom.starts_with(b"NES\x1A") => {
+        match apply_fn(&rom[INES_HEADER_SIZE..], patch) {
+          Ok(target) => {
+            let mut headered = rom[..INES_HEADER_SIZE].to_vec();
+            headered.extend_from_slice(&target);
+            Ok(headered)
+          },
+          Err(_) => Err(e),
+        }
+      },
+      Err(e) => Err(e),
+    }
+  }
+  else {
+    patch_error("unknown patch format")
+  }
+}
+
+struct PatchReader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+  fn new(data: &'a [u8], pos: usize) -> Self {
+    Self {
+      data,
+      pos,
+    }
+  }
+
+  fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+    if self.pos + len > self.data.len() {
+      return patch_error("unexpected end of patch");
+    }
+    let r = &self.data[self.pos..(self.pos + len)];
+    self.pos += len;
+    Ok(r)
+  }
+
+  fn byte(&mut self) -> Result<u8, Box<dyn Error>> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn be(&mut self, len: usize) -> Result<usize, Box<dyn Error>> {
+    Ok(self.bytes(len)?.iter().fold(0, |acc, b| (acc << 8) | (*b as usize)))
+  }
+
+  fn le_u32(&mut self) -> Result<u32, Box<dyn Error>> {
+    let b = self.bytes(4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+  }
+
+  /// UPS/BPS variable length number
+  fn varint(&mut self) -> Result<usize, Box<dyn Error>> {
+    let mut value: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+      let x = self.byte()?;
+      value = value.checked_add(((x & 0x7F) as usize).checked_mul(shift).ok_or_else(|| ErrorPatch::new("bad number"))?)
+        .ok_or_else(|| ErrorPatch::new("bad number"))?;
+      if x & 0x80 != 0 {
+        break;
+      }
+      shift = shift.checked_shl(7).ok_or_else(|| ErrorPatch::new("bad number"))?;
+      value = value.checked_add(shift).ok_or_else(|| ErrorPatch::new("bad number"))?;
+    }
+    Ok(value)
+  }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  let mut target = rom.to_vec();
+  let mut reader = PatchReader::new(patch, IPS_MAGIC.len());
+  loop {
+    if reader.bytes(3)? == IPS_EOF {
+      break;
+    }
+    reader.pos -= 3;
+    let offset = reader.be(3)?;
+    let size = reader.be(2)?;
+    let (len, rle_value) = if size == 0 {
+      (reader.be(2)?, Some(reader.byte()?))
+    }
+    else {
+      (size, None)
+    };
+    if target.len() < offset + len {
+      target.resize(offset + len, 0);
+    }
+    match rle_value {
+      Some(value) => target[offset..(offset + len)].fill(value),
+      None => target[offset..(offset + len)].copy_from_slice(reader.bytes(len)?),
+    }
+  }
+  // optional truncation extension
+  if patch.len() - reader.pos >= 3 {
+    let size = reader.be(3)?;
+    target.truncate(size);
+  }
+  Ok(target)
+}
+
+/// Builds an IPS patch turning `source` into `target`.
+pub fn create_ips(source: &[u8], target: &[u8]) -> Vec<u8> {
+  let mut patch = IPS_MAGIC.to_vec();
+  let mut pos = 0;
+  while pos < target.len() {
+    if source.get(pos) == Some(&target[pos]) {
+      pos += 1;
+      continue;
+    }
+    let start = if pos == IPS_EOF_OFFSET {pos - 1} else {pos};
+    let mut end = pos;
+    while end < target.len() && end - start < IPS_MAX_RECORD && source.get(end) != Some(&target[end]) {
+      end += 1;
+    }
+    patch.extend_from_slice(&(start as u32).to_be_bytes()[1..]);
+    patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+    patch.extend_from_slice(&target[start..end]);
+    pos = end;
+  }
+  patch.extend_from_slice(IPS_EOF);
+  if target.len() < source.len() {
+    patch.extend_from_slice(&(target.len() as u32).to_be_bytes()[1..]);
+  }
+  patch
+}
+
+fn check_footer(source: &[u8], target: &[u8], patch: &[u8]) -> Result<(), Box<dyn Error>> {
+  let mut reader = PatchReader::new(patch, patch.len() - FOOTER_SIZE);
+  let source_crc = reader.le_u32()?;
+  let target_crc = reader.le_u32()?;
+  let patch_crc = reader.le_u32()?;
+  if hash::crc32(&patch[..(patch.len() - 4)]) != patch_crc {
+    return patch_error("patch checksum mismatch, the patch file is corrupted");
+  }
+  if hash::crc32(source) != source_crc {
+    return patch_error("source checksum mismatch, the patch is for a different rom");
+  }
+  if hash::crc32(target) != target_crc {
+    return patch_error("target checksum mismatch after patching");
+  }
+  Ok(())
+}
+
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  if patch.len() < UPS_MAGIC.len() + FOOTER_SIZE {
+    return patch_error("UPS patch too short");
+  }
+  let end = patch.len() - FOOTER_SIZE;
+  let mut reader = PatchReader::new(&patch[..end], UPS_MAGIC.len());
+  let source_size = reader.varint()?;
+  let target_size = reader.varint()?;
+  if source_size != rom.len() {
+    return patch_error("source size mismatch, the patch is for a different rom");
+  }
+  if target_size > MAX_TARGET_SIZE {
+    return patch_error("UPS target too large");
+  }
+  let mut target = rom.to_vec();
+  target.resize(target_size, 0);
+
+  let mut pos: usize = 0;
+  while reader.pos < end {
+    pos = pos.checked_add(reader.varint()?).ok_or_else(|| ErrorPatch::new("bad number"))?;
+    loop {
+      let x = reader.byte()?;
+      if x == 0 {
+        pos += 1;
+        break;
+      }
+      if pos < target.len() {
+        target[pos] ^= x;
+      }
+      pos += 1;
+    }
+  }
+  check_footer(rom, &target, patch)?;
+  Ok(target)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+  if patch.len() < BPS_MAGIC.len() + FOOTER_SIZE {
+    return patch_error("BPS patch too short");
+  }
+  let end = patch.len() - FOOTER_SIZE;
+  let mut reader = PatchReader::new(&patch[..end], BPS_MAGIC.len());
+  let source_size = reader.varint()?;
+  let target_size = reader.varint()?;
+  let metadata_size = reader.varint()?;
+  reader.bytes(metadata_size)?;
+  if source_size != rom.len() {
+    return patch_error("source size mismatch, the patch is for a different rom");
+  }
+  if target_size > MAX_TARGET_SIZE {
+    return patch_error("BPS target too large");
+  }
+
+  let mut target: Vec<u8> = Vec::with_capacity(target_size);
+  let mut source_relative: isize = 0;
+  let mut target_relative: isize = 0;
+  while reader.pos < end {
+    let data = reader.varint()?;
+    let len = (data >> 2) + 1;
+    // every action appends `len` bytes
+    if target.len() + len > target_size {
+      return patch_error("BPS target size exceeded");
+    }
+    match data & 3 {
+      0 => {
+        let start = target.len();
+        if start + len > rom.len() {
+          return patch_error("BPS source read out of range");
+        }
+        target.extend_from_slice(&rom[start..(start + len)]);
+      },
+      1 => target.extend_from_slice(reader.bytes(len)?),
+      2 => {
+        let d = reader.varint()?;
+        let offset = (d >> 1) as isize;
+        source_relative = source_relative.checked_add(if d & 1 != 0 {-offset} else {offset})
+          .ok_or_else(|| ErrorPatch::new("bad number"))?;
+        if source_relative < 0 || source_relative as usize + len > rom.len() {
+          return patch_error("BPS source copy out of range");
+        }
+        let start = source_relative as usize;
+        target.extend_from_slice(&rom[start..(start + len)]);
+        source_relative += len as isize;
+      },
+      _ => {
+        let d = reader.varint()?;
+        let offset = (d >> 1) as isize;
+        target_relative = target_relative.checked_add(if d & 1 != 0 {-offset} else {offset})
+          .ok_or_else(|| ErrorPatch::new("bad number"))?;
+        if target_relative < 0 || target_relative as usize >= target.len() {
+          return patch_error("BPS target copy out of range");
+        }
+        // byte by byte, the copy can overlap the bytes being written
+        for _ in 0..len {
+          let v = target[target_relative as usize];
+          target.push(v);
+          target_relative += 1;
+        }
+      },
+    }
+  }
+  if target.len() != target_size {
+    return patch_error("BPS target size mismatch");
+  }
+  check_footer(rom, &target, patch)?;
+  Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn varint(mut value: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+      let x = (value & 0x7F) as u8;
+      value >>= 7;
+      if value == 0 {
+        out.push(0x80 | x);
+        return out;
+      }
+      out.push(x);
+      value -= 1;
+    }
+  }
+
+  fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+    patch.extend_from_slice(&hash::crc32(source).to_le_bytes());
+    patch.extend_from_slice(&hash::crc32(target).to_le_bytes());
+    let patch_crc = hash::crc32(&patch);
+    patch.extend_from_slice(&patch_crc.to_le_bytes());
+    patch
+  }
+
+  const BPS_SOURCE: [u8; 8] = [1, 2, 3, 4, 5, 6, 7, 8];
+  const BPS_TARGET: [u8; 12] = [1, 2, 3, 4, 0xAA, 0xBB, 5, 6, 5, 6, 5, 6];
+
+  fn bps_patch() -> Vec<u8> {
+    let mut patch = BPS_MAGIC.to_vec();
+    patch.extend(varint(BPS_SOURCE.len()));
+    patch.extend(varint(BPS_TARGET.len()));
+    patch.extend(varint(0));
+    // SourceRead 4
+    patch.extend(varint(3 << 2));
+    // TargetRead 2
+    patch.extend(varint((1 << 2) | 1));
+    patch.extend_from_slice(&[0xAA, 0xBB]);
+    // SourceCopy 2 from source offset +4
+    patch.extend(varint((1 << 2) | 2));
+    patch.extend(varint(4 << 1));
+    // TargetCopy 4 from target offset +6, overlapping the bytes it writes
+    patch.extend(varint((3 << 2) | 3));
+    patch.extend(varint(6 << 1));
+    with_footer(patch, &BPS_SOURCE, &BPS_TARGET)
+  }
+
+  fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = UPS_MAGIC.to_vec();
+    patch.extend(varint(source.len()));
+    patch.extend(varint(target.len()));
+    // xor 5 at 1, then xor 5, 6 at 4-5 (past the end of the source)
+    patch.extend(varint(1));
+    patch.extend_from_slice(&[5, 0]);
+    patch.extend(varint(1));
+    patch.extend_from_slice(&[5, 6, 0]);
+    with_footer(patch, source, target)
+  }
+
+  #[test]
+  fn ips_records() {
+    let mut patch = IPS_MAGIC.to_vec();
+    patch.extend_from_slice(&[0, 0, 2, 0, 2, 1, 2]);
+    // RLE record extending the rom
+    patch.extend_from_slice(&[0, 0, 6, 0, 0, 0, 4, 9]);
+    patch.extend_from_slice(IPS_EOF);
+    assert_eq!(apply(&[0; 8], &patch).unwrap(), [0, 0, 1, 2, 0, 0, 9, 9, 9, 9]);
+  }
+
+  #[test]
+  fn ips_truncation() {
+    let mut patch = IPS_MAGIC.to_vec();
+    patch.extend_from_slice(IPS_EOF);
+    patch.extend_from_slice(&[0, 0, 5]);
+    assert_eq!(apply(&[1, 2, 3, 4, 5, 6, 7, 8], &patch).unwrap(), [1, 2, 3, 4, 5]);
+  }
+
+  #[test]
+  fn ips_create_round_trip() {
+    let source: Vec<u8> = (0..64).collect();
+    let mut target = source[..48].to_vec();
+    target[3] = 0xFF;
+    target[40..44].fill(0);
+    assert_eq!(apply(&source, &create_ips(&source, &target)).unwrap(), target);
+    let mut longer = source.clone();
+    longer.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(apply(&source, &create_ips(&source, &longer)).unwrap(), longer);
+  }
+
+  #[test]
+  fn ups_xor_records() {
+    let source = [1, 2, 3, 4];
+    let target = [1, 7, 3, 4, 5, 6];
+    assert_eq!(apply(&source, &ups_patch(&source, &target)).unwrap(), target);
+  }
+
+  #[test]
+  fn ups_bad_footer() {
+    let source = [1, 2, 3, 4];
+    let target = [1, 7, 3, 4, 5, 6];
+    let mut patch = ups_patch(&source, &target);
+    let last = patch.len() - 1;
+    patch[last] ^= 1;
+    assert!(apply(&source, &patch).unwrap_err().to_string().contains("patch checksum"));
+    let patch = ups_patch(&source, &target);
+    assert!(apply(&[1, 2, 3, 5], &patch).unwrap_err().to_string().contains("source checksum"));
+  }
+
+  #[test]
+  fn ups_target_too_large() {
+    let mut patch = UPS_MAGIC.to_vec();
+    patch.extend(varint(0));
+    patch.extend(varint(1 << 40));
+    patch.extend_from_slice(&[0; FOOTER_SIZE]);
+    assert!(apply(&[], &patch).unwrap_err().to_string().contains("too large"));
+  }
+
+  #[test]
+  fn bps_actions() {
+    assert_eq!(apply(&BPS_SOURCE, &bps_patch()).unwrap(), BPS_TARGET);
+  }
+
+  #[test]
+  fn bps_bad_footer() {
+    let mut patch = bps_patch();
+    let crc_pos = patch.len() - 8;
+    patch[crc_pos] ^= 1;
+    assert!(apply(&BPS_SOURCE, &patch).is_err());
+    let mut source = BPS_SOURCE;
+    source[7] = 0;
+    assert!(apply(&source, &bps_patch()).unwrap_err().to_string().contains("source checksum"));
+  }
+
+  #[test]
+  fn bps_headerless_retry() {
+    let mut rom = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    rom.extend_from_slice(&BPS_SOURCE);
+    let patched = apply(&rom, &bps_patch()).unwrap();
+    assert_eq!(patched[..INES_HEADER_SIZE], rom[..INES_HEADER_SIZE]);
+    assert_eq!(patched[INES_HEADER_SIZE..], BPS_TARGET);
+  }
+
+  #[test]
+  fn bps_target_size_exceeded() {
+    for action in [varint(((1 << 40) << 2) | 1), [varint((1000 << 2) | 3), varint(0)].concat()] {
+      let mut patch = BPS_MAGIC.to_vec();
+      patch.extend(varint(0));
+      patch.extend(varint(1));
+      patch.extend(varint(0));
+      patch.extend(action);
+      patch.extend_from_slice(&[0; FOOTER_SIZE]);
+      assert!(apply(&[], &patch).unwrap_err().to_string().contains("size exceeded"));
+    }
+  }
+
+  #[test]
+  fn varint_overflow() {
+    let mut data = vec![0x7F; 12];
+    data.push(0x80);
+    assert!(PatchReader::new(&data, 0).varint().unwrap_err().to_string().contains("bad number"));
+    assert_eq!(PatchReader::new(&varint(0x12345), 0).varint().unwrap(), 0x12345);
+  }
+}
@@ -0,0 +1,273 @@
+use crate::nes::cartridge::{hash, RomError};
+
+pub const MAGIC: &[u8] = b"UNIF";
+const HEADER_SIZE: usize = 32;
+const CHUNK_HEADER_SIZE: usize = 8;
+const PRG_UNIT: usize = 16 * 1024;
+const CHR_UNIT: usize = 8 * 1024;
+// NES 2.0 RAM shift count, 64 << 7 = 8KB
+const RAM_8K_SHIFT: u8 = 7;
+
+
+/// UNIF board name (without the NES-/HVC-/UNL- style prefix) to iNES mapper and submapper.
+const BOARD_TABLE: &[(&str, u16, u8)] = &[
+  ("NROM", 0, 0),
+  ("NROM-128", 0, 0),
+  ("NROM-256", 0, 0),
+  ("RROM", 0, 0),
+  ("RROM-128", 0, 0),
+
+  ("SAROM", 1, 0),
+  ("SBROM", 1, 0),
+  ("SCROM", 1, 0),
+  ("SEROM", 1, 5),
+  ("SFROM", 1, 0),
+  ("SGROM", 1, 0),
+  ("SHROM", 1, 5),
+  ("SJROM", 1, 0),
+  ("SKROM", 1, 0),
+  ("SLROM", 1, 0),
+  ("SL1ROM", 1, 0),
+  ("SNROM", 1, 0),
+  ("SOROM", 1, 0),
+  ("SUROM", 1, 0),
+  ("SXROM", 1, 0),
+
+  ("UNROM", 2, 0),
+  ("UOROM", 2, 0),
+
+  ("CNROM", 3, 0),
+
+  ("TFROM", 4, 0),
+  ("TGROM", 4, 0),
+  ("TKROM", 4, 0),
+  ("TLROM", 4, 0),
+  ("TL1ROM", 4, 0),
+  ("TR1ROM", 4, 0),
+  ("TSROM", 4, 0),
+  ("TQROM", 119, 0),
+  ("TVROM", 4, 0),
+
+  ("EKROM", 5, 0),
+  ("ELROM", 5, 0),
+  ("ETROM", 5, 0),
+  ("EWROM", 5, 0),
+
+  ("AMROM", 7, 0),
+  ("ANROM", 7, 0),
+  ("AN1ROM", 7, 0),
+  ("AOROM", 7, 0),
+
+  ("PNROM", 9, 0),
+  ("FJROM", 10, 0),
+  ("FKROM", 10, 0),
+
+  ("GNROM", 66, 0),
+  ("MHROM", 66, 0),
+
+  ("CAMERICA-BF9093", 71, 0),
+  ("CAMERICA-BF9097", 71, 1),
+];
+
+const BOARD_PREFIXES: &[&str] = &["NES-", "HVC-", "UNL-", "BMC-", "BTL-", "IREM-", "KONAMI-", "TENGEN-"];
+
+pub fn board_to_mapper(board: &str) -> Option<(u16, u8)> {
+  let mut name = board.trim();
+  for prefix in BOARD_PREFIXES {
+    if name.get(..prefix.len()).is_some_and(|start| start.eq_ignore_ascii_case(prefix)) {
+      name = &name[prefix.len()..];
+      break;
+    }
+  }
+  BOARD_TABLE.iter()
+    .find(|(table_name, _, _)| table_name.eq_ignore_ascii_case(name))
+    .map(|(_, mapper_num, submapper_num)| (*mapper_num, *submapper_num))
+}
+
+fn chunk_index(id: &[u8], kind: &[u8; 3]) -> Option<usize> {
+  if &id[..3] == kind {
+    (id[3] as char).to_digit(16).map(|i| i as usize)
+  }
+  else {
+    None
+  }
+}
+
+fn c_string(data: &[u8]) -> String {
+  let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+  String::from_utf8_lossy(&data[..end]).to_string()
+}
+
+/// Converts a UNIF image to an in-memory NES 2.0 image, so it goes through
+/// the same `Cartridge::create_from_rom` path as an iNES file.
+pub fn to_ines(file: &[u8]) -> Result<Vec<u8>, RomError> {
+  if file.len() < HEADER_SIZE || &file[..4] != MAGIC {
+    return Err(RomError::TooShort(file.len()));
+  }
+  let revision = u32::from_le_bytes([file[4], file[5], file[6], file[7]]);
+
+  let mut board: Option<String> = None;
+  let mut prg: Vec<Option<Vec<u8>>> = vec![None; 16];
+  let mut chr: Vec<Option<Vec<u8>>> = vec![None; 16];
+  let mut prg_crc: Vec<Option<u32>> = vec![None; 16];
+  let mut chr_crc: Vec<Option<u32>> = vec![None; 16];
+  let mut mirroring: u8 = 0;
+  let mut battery = false;
+  let mut tv_system: u8 = 0;
+
+  let mut pos = HEADER_SIZE;
+  while pos + CHUNK_HEADER_SIZE <= file.len() {
+    let id = &file[pos..(pos + 4)];
+    let len = u32::from_le_bytes([file[pos + 4], file[pos + 5], file[pos + 6], file[pos + 7]]) as usize;
+    let start = pos + CHUNK_HEADER_SIZE;
+    if start + len > file.len() {
+      return Err(RomError::Malformed(format!("UNIF chunk {} is truncated", String::from_utf8_lossy(id))));
+    }
+    let data = &file[start..(start + len)];
+    match id {
+      b"MAPR" => board = Some(c_string(data)),
+      b"NAME" => println!("UNIF name: {}", c_string(data)),
+      b"MIRR" if len > 0 => mirroring = data[0],
+      b"BATR" => battery = len == 0 || data[0] != 0,
+      b"TVCI" if len > 0 => tv_system = data[0],
+      _ => {
+        if let Some(i) = chunk_index(id, b"PRG") {
+          prg[i] = Some(data.to_vec());
+        }
+        else if let Some(i) = chunk_index(id, b"CHR") {
+          chr[i] = Some(data.to_vec());
+        }
+        else if let (Some(i), true) = (chunk_index(id, b"PCK"), len >= 4) {
+          prg_crc[i] = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+        }
+        else if let (Some(i), true) = (chunk_index(id, b"CCK"), len >= 4) {
+          chr_crc[i] = Some(u32::from_le_bytes([data[0], data[1], data[2], data[3]]));
+        }
+      },
+    }
+    pos = start + len;
+  }
+
+  let board = board.ok_or_else(|| RomError::Malformed("UNIF MAPR chunk is missing".to_string()))?;
+  let (mapper_num, submapper_num) = board_to_mapper(&board)
+    .ok_or_else(|| RomError::UnsupportedFormat(format!("UNIF board {}", board)))?;
+  println!("UNIF revision {}, board {} => mapper {}.{}", revision, board, mapper_num, submapper_num);
+
+  for (i, (data, crc)) in prg.iter().zip(&prg_crc).chain(chr.iter().zip(&chr_crc)).enumerate() {
+    if let (Some(data), Some(crc)) = (data, crc) {
+      if hash::crc32(data) != *crc {
+        println!("UNIF warning: {}{:X} checksum mismatch", if i < 16 {"PRG"} else {"CHR"}, i % 16);
+      }
+    }
+  }
+
+  let mut prg_rom: Vec<u8> = prg.into_iter().flatten().flatten().collect();
+  let mut chr_rom: Vec<u8> = chr.into_iter().flatten().flatten().collect();
+  if prg_rom.is_empty() {
+    return Err(RomError::Malformed("UNIF PRG chunk is missing".to_string()));
+  }
+  prg_rom.resize(prg_rom.len().div_ceil(PRG_UNIT) * PRG_UNIT, 0);
+  chr_rom.resize(chr_rom.len().div_ceil(CHR_UNIT) * CHR_UNIT, 0);
+  let prg_units = prg_rom.len() / PRG_UNIT;
+  let chr_units = chr_rom.len() / CHR_UNIT;
+
+  let mut header = [0u8; 16];
+  header[..4].copy_from_slice(b"NES\x1A");
+  header[4] = (prg_units & 0xFF) as u8;
+  header[5] = (chr_units & 0xFF) as u8;
+  header[6] = (((mapper_num & 0x0F) as u8) << 4)
+    | if battery {0b0000_0010} else {0}
+    | match mirroring {
+      1 => 0b0000_0001,
+      4 => 0b0000_1000,
+      _ => 0,
+    };
+  header[7] = ((mapper_num & 0xF0) as u8) | 0b0000_1000;
+  header[8] = (submapper_num << 4) | (((mapper_num >> 8) & 0x0F) as u8);
+  header[9] = (((prg_units >> 8) & 0x0F) as u8) | ((((chr_units >> 8) & 0x0F) as u8) << 4);
+  header[10] = if battery {RAM_8K_SHIFT << 4} else {RAM_8K_SHIFT};
+  header[11] = if chr_rom.is_empty() {RAM_8K_SHIFT} else {0};
+  header[12] = match tv_system {
+    1 => 1,
+    2 => 2,
+    _ => 0,
+  };
+
+  let mut rom = header.to_vec();
+  rom.extend_from_slice(&prg_rom);
+  rom.extend_from_slice(&chr_rom);
+  Ok(rom)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::nes::cartridge::{NesHeader, TimingType};
+
+  fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+  }
+
+  fn unif(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut file = MAGIC.to_vec();
+    file.extend_from_slice(&7u32.to_le_bytes());
+    file.resize(HEADER_SIZE, 0);
+    for c in chunks {
+      file.extend_from_slice(c);
+    }
+    file
+  }
+
+  #[test]
+  fn board_names() {
+    assert_eq!(board_to_mapper("NES-SLROM"), Some((1, 0)));
+    assert_eq!(board_to_mapper("HVC-TQROM"), Some((119, 0)));
+    assert_eq!(board_to_mapper(" unl-camerica-bf9097 "), Some((71, 1)));
+    assert_eq!(board_to_mapper("NES-XYZROM"), None);
+  }
+
+  #[test]
+  fn chunks_to_nes2() {
+    let prg1 = vec![1; PRG_UNIT / 2];
+    let file = unif(&[
+      chunk(b"MAPR", b"NES-UNROM\0"),
+      chunk(b"NAME", b"Test\0"),
+      // the banks are ordered by index, not by position in the file
+      chunk(b"PRG1", &prg1),
+      chunk(b"PRG0", &[0; PRG_UNIT]),
+      chunk(b"PCK1", &hash::crc32(&prg1).to_le_bytes()),
+      chunk(b"MIRR", &[1]),
+      chunk(b"BATR", &[1]),
+      chunk(b"TVCI", &[1]),
+      chunk(b"DINF", &[0; 204]),
+    ]);
+    let rom = to_ines(&file).unwrap();
+    let header = NesHeader::new(&rom[..16]);
+    assert!(header.nes2);
+    assert_eq!((header.mapper_num, header.submapper_num), (2, 0));
+    assert_eq!(rom[6] & 0b0000_1001, 0b0000_0001, "vertical mirroring");
+    assert!(header.battery);
+    assert_eq!(header.timing_type, TimingType::PAL_NES);
+    assert_eq!(header.eeprom_size, 0x2000);
+    assert_eq!(header.chr_ram_size, 0x2000);
+    // 24KB of PRG-ROM, padded to 32KB
+    assert_eq!((header.prg_rom_size, header.chr_rom_size), (2 * PRG_UNIT, 0));
+    assert_eq!(rom.len(), 16 + 2 * PRG_UNIT);
+    assert!(rom[16..(16 + PRG_UNIT)].iter().all(|b| *b == 0));
+    assert_eq!(rom[(16 + PRG_UNIT)..(16 + PRG_UNIT + prg1.len())], prg1[..]);
+    assert!(rom[(16 + PRG_UNIT + prg1.len())..].iter().all(|b| *b == 0));
+  }
+
+  #[test]
+  fn malformed_files() {
+    let file = unif(&[chunk(b"MAPR", b"NES-NROM-256\0"), chunk(b"PRG0", &[0; 16])]);
+    assert!(matches!(to_ines(&file[..(file.len() - 4)]), Err(RomError::Malformed(_))));
+    assert!(matches!(to_ines(&unif(&[chunk(b"PRG0", &[0; 16])])), Err(RomError::Malformed(_))));
+    assert!(matches!(to_ines(&unif(&[chunk(b"MAPR", b"NES-NROM\0")])), Err(RomError::Malformed(_))));
+    assert!(matches!(to_ines(&unif(&[chunk(b"MAPR", b"NES-XYZROM\0"), chunk(b"PRG0", &[0; 16])])),
+      Err(RomError::UnsupportedFormat(_))));
+  }
+}